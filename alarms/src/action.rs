@@ -0,0 +1,262 @@
+use crate::{
+    scheduler::Context, Action, ActionKind, AlarmKey, AlarmState, Deployment, Error, Result,
+    Service,
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, PoisonError},
+};
+use strum::Display;
+use tracing::{error, info, warn};
+
+/// Results kept to be listed by the API, the oldest are dropped first
+const MAX_RESULTS: usize = 100;
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Display)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum ActionStatus {
+    /// Skipped, the action already ran within its cooldown
+    CoolingDown,
+    /// Would have run, but the service was left untouched
+    DryRun,
+    Executed,
+    Failed,
+    /// Skipped, the action already ran its maximum executions within the window
+    LimitReached,
+}
+
+/// Outcome of an action, recorded and sent along with the notification of the alarm that triggered it
+#[derive(Getters, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ActionResult {
+    #[serde(flatten)]
+    key: AlarmKey,
+    #[copy]
+    kind: ActionKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[copy]
+    replicas: Option<u16>,
+    #[copy]
+    status: ActionStatus,
+    /// Why the action failed, or what it changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[copy]
+    date: DateTime<Utc>,
+}
+
+/// Runs the actions of the alarms that enter the ALARM state, within their cooldowns and limits
+pub struct Actions {
+    actions: HashMap<AlarmKey, Action>,
+    /// Dates each action ran within its window, dry runs and failures included
+    executions: HashMap<AlarmKey, VecDeque<DateTime<Utc>>>,
+    results: Arc<Mutex<VecDeque<ActionResult>>>,
+}
+
+impl ActionResult {
+    pub fn new(
+        key: AlarmKey,
+        kind: ActionKind,
+        replicas: Option<u16>,
+        status: ActionStatus,
+        message: Option<String>,
+        date: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            key,
+            kind,
+            replicas,
+            status,
+            message,
+            date,
+        }
+    }
+}
+
+impl Actions {
+    pub fn new(actions: HashMap<AlarmKey, Action>) -> Self {
+        Self {
+            actions,
+            executions: HashMap::new(),
+            results: Arc::default(),
+        }
+    }
+
+    /// Latest results, shared with the API
+    pub fn results(&self) -> Arc<Mutex<VecDeque<ActionResult>>> {
+        Arc::clone(&self.results)
+    }
+
+    /// Returns the result of the action of each alarm that entered the ALARM state
+    pub async fn run(
+        &mut self,
+        alarms: &HashMap<AlarmKey, AlarmState>,
+        context: &Context,
+        now: DateTime<Utc>,
+    ) -> Vec<ActionResult> {
+        let mut results = Vec::new();
+        for (key, state) in alarms {
+            if !state.on() {
+                continue;
+            }
+
+            // Actions of alarms per replica act on the whole service, so replicas share them
+            let action_key = AlarmKey::new(key.alarm(), key.name().clone(), None);
+            let Some(action) = self.actions.get(&action_key).copied() else {
+                continue;
+            };
+
+            let executions = self.executions.entry(action_key).or_default();
+            let window_start = now - TimeDelta::minutes(action.window_minutes().into());
+            while executions.front().is_some_and(|date| *date < window_start) {
+                executions.pop_front();
+            }
+
+            let cooldown = TimeDelta::minutes(action.cooldown_minutes().into());
+            let (status, message) = if executions.back().is_some_and(|date| *date + cooldown > now)
+            {
+                (ActionStatus::CoolingDown, None)
+            } else if executions.len() >= usize::from(action.max_executions()) {
+                (ActionStatus::LimitReached, None)
+            } else if action.dry_run() {
+                executions.push_back(now);
+                (ActionStatus::DryRun, None)
+            } else {
+                executions.push_back(now);
+                match execute(action, context).await {
+                    Ok(()) => (ActionStatus::Executed, None),
+                    Err(err) => (ActionStatus::Failed, Some(err.to_string())),
+                }
+            };
+
+            let result = ActionResult {
+                key: key.clone(),
+                kind: action.kind(),
+                replicas: action.replicas(),
+                status,
+                message,
+                date: now,
+            };
+            match status {
+                ActionStatus::Failed => error!(
+                    "Action {} of {key} failed: {:?}",
+                    result.kind, result.message
+                ),
+                ActionStatus::CoolingDown | ActionStatus::LimitReached => {
+                    warn!("Action {} of {key} skipped: {status}", result.kind)
+                }
+                ActionStatus::DryRun | ActionStatus::Executed => {
+                    info!("Action {} of {key}: {status}", result.kind)
+                }
+            }
+            results.push(result);
+        }
+
+        self.record(&results);
+        results
+    }
+
+    /// Keeps results of actions taken elsewhere, so the API lists them too
+    pub fn record(&self, results: &[ActionResult]) {
+        let mut recorded = self.results.lock().unwrap_or_else(PoisonError::into_inner);
+        recorded.extend(results.iter().cloned());
+        while recorded.len() > MAX_RESULTS {
+            recorded.pop_front();
+        }
+    }
+}
+
+async fn execute(action: Action, context: &Context) -> Result<()> {
+    let railway = &context.railway;
+    let latest = || async {
+        Deployment::current(railway, &context.project_id, &context.service_id)
+            .await?
+            .ok_or_else(|| Error::DeploymentMissing(context.service_id.clone()))
+    };
+
+    match action.kind() {
+        ActionKind::Redeploy => latest().await?.redeploy(railway).await?,
+        ActionKind::Restart => latest().await?.restart(railway).await?,
+        ActionKind::Stop => latest().await?.stop(railway).await?,
+        ActionKind::Replicas => {
+            let environment_id = std::env::var("RAILWAY_ENVIRONMENT_ID")
+                .map_err(|_| Error::MissingEnvVar("RAILWAY_ENVIRONMENT_ID"))?;
+            let replicas = action
+                .replicas()
+                .ok_or_else(|| Error::MissingSetting("ACTION_REPLICAS".to_owned()))?;
+            Service::set_replicas(railway, &environment_id, &context.service_id, replicas).await?
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ActionStatus, Actions};
+    use crate::{
+        scheduler::Context, Action, ActionKind, Alarm, AlarmKey, AlarmState, FakeClock,
+        FakeRailway, RailwayClient,
+    };
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use std::{collections::HashMap, sync::Arc};
+
+    #[tokio::test]
+    async fn run() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let railway = Arc::new(FakeRailway::new());
+        railway.respond(
+            "deployments",
+            serde_json::json!({
+                "deployments": {
+                    "edges": [{ "node": { "id": "deployment", "status": "SUCCESS", "createdAt": now } }],
+                },
+            }),
+        );
+        railway.respond("restart", serde_json::json!({ "deploymentRestart": true }));
+        let context = Context {
+            clock: Arc::new(FakeClock::new(now)),
+            railway: RailwayClient::with_transport(railway.clone()),
+            project_id: "project".to_owned(),
+            service_id: "service".to_owned(),
+        };
+
+        let key = AlarmKey::from(Alarm::MemoryUpperLimitGb);
+        let action = Action {
+            cooldown_minutes: 10,
+            max_executions: 2,
+            window_minutes: 60,
+            ..Action::new(ActionKind::Restart)
+        };
+        let mut actions = Actions::new(HashMap::from([(key.clone(), action)]));
+        // Per replica alarms share the action of the alarm
+        let alarms = HashMap::from([(
+            key.with_replica("replica".to_owned()),
+            AlarmState::new(key.with_replica("replica".to_owned()), true),
+        )]);
+
+        let mut statuses = Vec::new();
+        for minutes in [0, 5, 11, 22, 71] {
+            let results = actions
+                .run(&alarms, &context, now + TimeDelta::minutes(minutes))
+                .await;
+            statuses.extend(results.iter().map(|result| result.status()));
+        }
+        assert_eq!(
+            statuses,
+            [
+                ActionStatus::Executed,
+                ActionStatus::CoolingDown,
+                ActionStatus::Executed,
+                ActionStatus::LimitReached,
+                ActionStatus::Executed,
+            ]
+        );
+        assert_eq!(railway.requests("restart").len(), 3);
+        assert_eq!(railway.requests("restart")[0]["id"], "deployment");
+        assert_eq!(actions.results().lock().unwrap().len(), 5);
+    }
+}
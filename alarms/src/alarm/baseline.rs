@@ -0,0 +1,145 @@
+use crate::{Error, Result};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf};
+use tracing::{info, warn};
+
+/// One band for each hour of the week, so seasonal traffic has its own baseline
+const HOURS_PER_WEEK: usize = 7 * 24;
+
+/// Samples a band must learn before anomalies can be detected with it
+const MIN_SAMPLES: u32 = 30;
+
+/// Once a band has this many samples it becomes an exponentially weighted moving average,
+/// four weeks of samples per minute, so old samples slowly stop affecting it
+const MAX_SAMPLES: u32 = 4 * 60;
+
+/// Lower bound of the standard deviation, so a metric that never changed doesn't breach on any change
+const MIN_STANDARD_DEVIATION: f64 = 1e-6;
+
+/// Expected value of a metric at some hour of the week
+#[derive(Getters, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+pub struct Band {
+    #[copy]
+    mean: f64,
+    #[copy]
+    variance: f64,
+    #[copy]
+    samples: u32,
+}
+
+impl Band {
+    pub fn standard_deviation(self) -> f64 {
+        self.variance.sqrt().max(MIN_STANDARD_DEVIATION)
+    }
+
+    /// Number of standard deviations the value is away from the mean
+    pub fn deviations(self, value: f64) -> f64 {
+        (value - self.mean).abs() / self.standard_deviation()
+    }
+
+    fn learn(&mut self, value: f64) {
+        self.samples = (self.samples + 1).min(MAX_SAMPLES);
+
+        let alpha = 1. / f64::from(self.samples);
+        let delta = value - self.mean;
+        self.mean += alpha * delta;
+        self.variance = (1. - alpha) * (self.variance + alpha * delta * delta);
+    }
+}
+
+/// Bands learned for each variable, persisted so they survive restarts
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Baselines {
+    #[serde(skip)]
+    path: PathBuf,
+    variables: HashMap<String, Vec<Band>>,
+}
+
+impl Baselines {
+    /// Starts learning from scratch if nothing was persisted or it can't be read
+    pub async fn load(path: PathBuf) -> Self {
+        let baselines = match tokio::fs::read(&path).await {
+            Ok(bytes) => match serde_json::from_slice::<Self>(&bytes) {
+                Ok(baselines) => {
+                    info!("Loaded baselines from {}", path.display());
+                    baselines
+                }
+                Err(err) => {
+                    warn!("Ignoring invalid baselines in {}: {err}", path.display());
+                    Self::default()
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(err) => {
+                warn!("Unable to read baselines from {}: {err}", path.display());
+                Self::default()
+            }
+        };
+
+        Self { path, ..baselines }
+    }
+
+    /// Writes a temporary file first so a crash mid-write can't truncate the persisted baselines
+    pub async fn save(&self) -> Result<()> {
+        let json = serde_json::to_vec(self)?;
+        let temporary = self.path.with_extension("tmp");
+        tokio::fs::write(&temporary, json)
+            .await
+            .map_err(|err| Error::IoWithMetadata(err, temporary.display().to_string()))?;
+        tokio::fs::rename(&temporary, &self.path)
+            .await
+            .map_err(|err| Error::IoWithMetadata(err, self.path.display().to_string()))
+    }
+
+    /// Returns `None` while the band of that hour hasn't learned enough samples
+    pub fn band(&self, variable: &str, date: DateTime<Utc>) -> Option<Band> {
+        let band = self.variables.get(variable)?.get(hour_of_week(date))?;
+        (band.samples >= MIN_SAMPLES).then_some(*band)
+    }
+
+    pub fn learn(&mut self, variable: &str, date: DateTime<Utc>, value: f64) {
+        let bands = self
+            .variables
+            .entry(variable.to_owned())
+            .or_insert_with(|| vec![Band::default(); HOURS_PER_WEEK]);
+        if let Some(band) = bands.get_mut(hour_of_week(date)) {
+            band.learn(value);
+        }
+    }
+}
+
+fn hour_of_week(date: DateTime<Utc>) -> usize {
+    date.weekday().num_days_from_monday() as usize * 24 + date.hour() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Baselines;
+    use chrono::{TimeDelta, TimeZone, Utc};
+
+    #[test]
+    fn band() {
+        let mut baselines = Baselines::default();
+        let monday = Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap();
+
+        for minute in 0..60 {
+            let value = if minute % 2 == 0 { 9. } else { 11. };
+            baselines.learn("ingress_gb", monday + TimeDelta::minutes(minute), value);
+        }
+
+        let band = baselines.band("ingress_gb", monday).expect("missing band");
+        assert!((band.mean() - 10.).abs() < 1e-9);
+        assert!((band.standard_deviation() - 1.).abs() < 1e-9);
+        assert!(band.deviations(14.) > 3.);
+        assert!(band.deviations(12.) < 3.);
+
+        // Other hours of the week have their own band
+        assert_eq!(
+            baselines.band("ingress_gb", monday + TimeDelta::hours(1)),
+            None
+        );
+        assert_eq!(baselines.band("egress_gb", monday), None);
+    }
+}
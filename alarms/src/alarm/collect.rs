@@ -1,18 +1,43 @@
-use crate::{Alarm, AlarmConfig, AlarmState, Service, Usage};
-use chrono::{DateTime, Utc};
+use crate::{
+    scheduler::{self, Context, SourceState},
+    Alarm, AlarmConfig, AlarmKey, AlarmState, Baselines, Deployment, DeploymentStatus, Error,
+    Expression, History, Log, Service, Statistic, Usage,
+};
+use chrono::{DateTime, TimeDelta, Utc};
 use derive_get::Getters;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+/// Railway's private network resolves this domain to the address of every replica of the service
+const PRIVATE_DOMAIN_SUFFIX: &str = ".railway.internal";
+
+/// Set by the monitored service to `RAILWAY_REPLICA_ID`, so a replica probed by its address is identified by its
+/// deployment instance, as in its usage
+const REPLICA_ID_HEADER: &str = "X-Replica-Id";
+
+/// Usage is sampled every minute, so the statistics of a period combine or pick its minutes
+const SAMPLE_SECS: u32 = 60;
+
+/// Windows of usage of each period missed while unable to reach Railway that are backfilled, older ones are skipped
+const MAX_BACKFILL_WINDOWS: i64 = 60;
 
 #[derive(Getters, Serialize, Deserialize, Clone, Debug)]
 pub struct AlarmPayload {
+    /// Samples collected during the current period, one per minute
+    samples: Vec<f64>,
+    /// Usage collected during the current period, used by expression alarms
+    usages: Vec<Usage>,
+    /// Seconds elapsed in the current period
     #[copy]
-    accumulated: f64,
-    #[copy]
-    minutes: u16,
+    secs: u32,
     measurements: VecDeque<bool>,
+    /// Whether each of the latest data points crossed back the recovery threshold
+    recoveries: VecDeque<bool>,
+    /// Whether the state changed on each of the latest data points, used to detect flapping
+    toggles: VecDeque<bool>,
     #[copy]
     state: bool,
     config: AlarmConfig,
@@ -21,189 +46,1496 @@ pub struct AlarmPayload {
 impl AlarmPayload {
     pub fn from_config(config: AlarmConfig) -> Self {
         Self {
-            accumulated: 0.,
-            minutes: 0,
+            samples: Vec::new(),
+            usages: Vec::new(),
+            secs: 0,
             measurements: VecDeque::new(),
+            recoveries: VecDeque::new(),
+            toggles: VecDeque::new(),
             state: false,
             config,
         }
     }
 }
 
-pub async fn alarms(
+/// Probes the healthcheck endpoint, the task runs on the period of the alarm so it's evaluated on every call
+pub async fn healthcheck_alarms(
+    alarm_payloads: &mut HashMap<AlarmKey, AlarmPayload>,
+    replica_ids: &mut HashMap<String, String>,
+    shutdown: &CancellationToken,
+) -> Option<HashMap<AlarmKey, AlarmState>> {
+    let mut alarms = HashMap::new();
+
+    if let Some(payload) = alarm_payloads.get(&Alarm::HealthCheckFailed.into()) {
+        let config = payload.config().clone();
+        let replicas = tokio::select! {
+            replicas = healthcheck(config.value(), config.per_replica(), replica_ids) => replicas,
+            _ = shutdown.cancelled() => return None,
+        };
+
+        process_healthcheck(&mut alarms, alarm_payloads, replicas);
+    }
+
+    Some(alarms)
+}
+
+/// Checks the state and the logs of the latest deployment
+pub async fn deployment_alarms(
     start_date: DateTime<Utc>,
-    alarm_payloads: &mut HashMap<Alarm, AlarmPayload>,
+    alarm_payloads: &mut HashMap<AlarmKey, AlarmPayload>,
     shutdown: &CancellationToken,
-    period_secs: u16,
-    railway_api_token: &str,
-    project_id: &str,
-    service_id: &str,
-) -> Option<HashMap<Alarm, AlarmState>> {
+    period_secs: u32,
+    context: &Context,
+) -> Option<HashMap<AlarmKey, AlarmState>> {
     let mut alarms = HashMap::new();
+    let railway = &context.railway;
+
+    let result = tokio::select! {
+        result = Deployment::latest(
+            railway,
+            &context.project_id,
+            &context.service_id,
+            start_date,
+            period_secs,
+        ) => result,
+        _ = shutdown.cancelled() => return None,
+    };
 
-    // Tests healthcheck endpoint
-    if let Some(payload) = alarm_payloads.get_mut(&Alarm::HealthCheckFailed) {
-        payload.minutes += period_secs / 60;
+    match result {
+        Ok(deployment) => {
+            if alarm_payloads
+                .keys()
+                .any(|key| key.alarm() == Alarm::LogMatched)
+            {
+                let result = match &deployment {
+                    Some(deployment) => tokio::select! {
+                        result = deployment.logs(railway, start_date, period_secs) => result,
+                        _ = shutdown.cancelled() => return None,
+                    },
+                    None => Ok(Vec::new()),
+                };
 
-        if payload.minutes() >= payload.config().period_minutes() {
-            payload.minutes = 0;
+                match result {
+                    Ok(logs) => process_logs(&mut alarms, &mut *alarm_payloads, logs, period_secs),
+                    Err(err) => error!("Unable to fetch logs from Railway: {err}"),
+                }
+            }
 
-            let is_on = tokio::select! {
-                is_on = healthcheck(payload.config().value()) => is_on,
-                _ = shutdown.cancelled() => return None,
-            };
+            process_deployment(&mut alarms, &mut *alarm_payloads, deployment, period_secs)
+        }
+        Err(err) => error!("Unable to fetch deployment from Railway: {err}"),
+    }
+
+    Some(alarms)
+}
 
-            process_healthcheck(&mut alarms, payload, is_on);
+/// Evaluates every alarm computed from the usage of the service, the alarms of each period once it elapses. The
+/// windows that elapsed, along with the ones that couldn't be measured since `unmeasured_since`, are fetched in a
+/// single query and split locally, so a short period doesn't make the others query more often and the missed windows
+/// are backfilled before the latest one. The samples of the shortest period, of the service and of each replica, are
+/// retained, learned and appended to `usages`
+pub async fn usage_alarms(
+    start_date: DateTime<Utc>,
+    alarm_payloads: &mut HashMap<AlarmKey, AlarmPayload>,
+    state: &mut SourceState,
+    usages: &mut Vec<Usage>,
+    shutdown: &CancellationToken,
+    tick_secs: u32,
+    context: &Context,
+) -> Option<HashMap<AlarmKey, AlarmState>> {
+    let mut alarms = HashMap::new();
+    let (railway, service_id) = (&context.railway, &context.service_id);
+    let SourceState {
+        history,
+        baselines,
+        unmeasured_since,
+        ..
+    } = state;
+
+    let end_date = start_date + TimeDelta::seconds(tick_secs.into());
+    let periods = periods(alarm_payloads.values());
+    let shortest = periods.first().copied();
+
+    // The tick divides all the periods, so the latest window of each ended by the end of the tick
+    let windows = windows(&periods, end_date, |period_secs, ended| {
+        let period = TimeDelta::seconds(period_secs.into());
+        let since = unmeasured_since
+            .entry(period_secs)
+            .or_insert(ended - period);
+
+        let missed = (ended - *since).num_seconds() / i64::from(period_secs) - 1;
+        if missed > MAX_BACKFILL_WINDOWS {
+            warn!(
+                "Unable to backfill {missed} windows of {period_secs} seconds, skipping the oldest"
+            );
+            *since = ended - period * (MAX_BACKFILL_WINDOWS + 1) as i32;
+        } else if missed > 0 {
+            info!("Backfilling {missed} windows of {period_secs} seconds of usage");
         }
+        *since
+    });
+    if windows.is_empty() {
+        return Some(alarms);
     }
 
+    // Limits are fetched every time, so thresholds follow when the service is resized
+    let limits = if alarm_payloads
+        .iter()
+        .any(|(key, payload)| needs_limits(key, payload.config()))
+    {
+        let result = match std::env::var("RAILWAY_ENVIRONMENT_ID") {
+            Ok(environment_id) => tokio::select! {
+                result = Service::limits(railway, &environment_id, service_id) => result.map_err(Error::from),
+                _ = shutdown.cancelled() => return None,
+            },
+            Err(_) => Err(Error::MissingEnvVar("RAILWAY_ENVIRONMENT_ID")),
+        };
+
+        match result {
+            Ok(limits) => Some(limits),
+            Err(err) => {
+                error!("Unable to fetch limits from Railway: {err}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let range_start = windows
+        .iter()
+        .map(|(window_end, period_secs)| *window_end - TimeDelta::seconds((*period_secs).into()))
+        .min()
+        .unwrap_or(end_date);
+    let range_end = windows
+        .iter()
+        .map(|(window_end, _)| *window_end)
+        .max()
+        .unwrap_or(end_date);
+
     // Gets usage or shuts-down if ctrl+c was received
     let result = tokio::select! {
-        result = Service::usage(
-            railway_api_token,
-            project_id,
+        result = Service::usage_samples(
+            railway,
+            &context.project_id,
             service_id,
-            start_date,
-            period_secs,
+            range_start,
+            range_end,
+            sample_secs(tick_secs),
         ) => result,
         _ = shutdown.cancelled() => return None,
     };
+    let mut samples = match result {
+        Ok(samples) => samples,
+        Err(err) => {
+            // The windows are evaluated in order once Railway answers again
+            error!("Unable to fetch usage from Railway, it will be backfilled: {err}");
+            return Some(alarms);
+        }
+    };
 
-    match result {
-        Ok(usage) => process_usage(&mut alarms, &mut *alarm_payloads, usage, period_secs),
-        Err(err) => error!("Unable to fetch usage from Railway: {err}"),
+    if let Some(limits) = limits {
+        for (usage, replicas) in &mut samples {
+            usage.set_limits(limits, replicas.len());
+            for replica in replicas {
+                replica.set_limits(limits, 1);
+            }
+        }
     }
 
-    let alarms_on = alarm_payloads
-        .iter()
-        .filter(|(_, payload)| payload.state())
-        .map(|(alarm, _)| alarm.to_string())
-        .collect::<Vec<_>>();
-    if !alarms_on.is_empty() {
-        info!("Alarms on: {}", alarms_on.join(", "));
+    for (window_end, period_secs) in windows {
+        let window_start = window_end - TimeDelta::seconds(period_secs.into());
+        let mut samples = within(&samples, window_start, window_end);
+        fill_replicas(&mut samples);
+        unmeasured_since.insert(period_secs, window_end);
+
+        evaluate_window(
+            &mut alarms,
+            alarm_payloads,
+            history,
+            baselines,
+            (shortest == Some(period_secs)).then_some(&mut *usages),
+            samples,
+            period_secs,
+        );
     }
 
     Some(alarms)
 }
 
-fn process_usage(
-    alarms: &mut HashMap<Alarm, AlarmState>,
-    alarm_payloads: &mut HashMap<Alarm, AlarmPayload>,
+/// Periods of the alarms, shortest first
+pub fn periods<'a>(alarm_payloads: impl IntoIterator<Item = &'a AlarmPayload>) -> Vec<u32> {
+    let mut periods = alarm_payloads
+        .into_iter()
+        .map(|payload| payload.config().period_secs())
+        .collect::<Vec<_>>();
+    periods.sort_unstable();
+    periods.dedup();
+    periods
+}
+
+/// Ends of the windows of each period elapsed by `end_date`, starting from the date `since` returns given the period
+/// and the end of its latest window. Periods end on multiples of their length. Ordered by end and then by period, so
+/// the history of the shortest period is retained before the trend alarms of the same date are evaluated
+pub fn windows(
+    periods: &[u32],
+    end_date: DateTime<Utc>,
+    mut since: impl FnMut(u32, DateTime<Utc>) -> DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, u32)> {
+    let mut windows = Vec::new();
+    for &period_secs in periods {
+        let period = TimeDelta::seconds(period_secs.into());
+        let elapsed = end_date.timestamp().rem_euclid(period_secs.into());
+        let ended = end_date - TimeDelta::seconds(elapsed);
+
+        let mut window_start = since(period_secs, ended);
+        while window_start + period <= ended {
+            windows.push((window_start + period, period_secs));
+            window_start += period;
+        }
+    }
+    windows.sort_unstable();
+    windows
+}
+
+/// Samples are taken every minute, or on a divisor of the tick if the periods aren't multiples of a minute
+pub fn sample_secs(tick_secs: u32) -> u32 {
+    scheduler::gcd(tick_secs, SAMPLE_SECS)
+}
+
+/// Samples measured during the window, `samples` are ordered oldest first
+pub fn within(
+    samples: &[(Usage, Vec<Usage>)],
+    start_date: DateTime<Utc>,
+    end_date: DateTime<Utc>,
+) -> Vec<(Usage, Vec<Usage>)> {
+    let from = samples.partition_point(|(usage, _)| usage.start_date() < start_date);
+    let to = samples.partition_point(|(usage, _)| usage.start_date() < end_date);
+    samples[from..to]
+        .iter()
+        .filter(|(usage, _)| usage.end_date() <= end_date)
+        .cloned()
+        .collect()
+}
+
+/// Replicas measured during part of a window are added empty to the samples they're missing from, so their alarms
+/// get every sample of the window and are evaluated at its end like the others
+pub fn fill_replicas(samples: &mut [(Usage, Vec<Usage>)]) {
+    let mut measured: Vec<Usage> = Vec::new();
+    for replica in samples.iter().flat_map(|(_, replicas)| replicas) {
+        if !measured
+            .iter()
+            .any(|usage| usage.replica() == replica.replica())
+        {
+            measured.push(replica.clone());
+        }
+    }
+
+    for (usage, replicas) in samples {
+        for replica in &measured {
+            if !replicas
+                .iter()
+                .any(|usage| usage.replica() == replica.replica())
+            {
+                replicas.push(replica.emptied(usage.start_date(), usage.end_date()));
+            }
+        }
+    }
+}
+
+/// Evaluates the alarms of the period at the end of a window, over each of the samples measured during it. Given
+/// `usages`, the samples are also retained in the history, learned and appended to it
+pub fn evaluate_window(
+    alarms: &mut HashMap<AlarmKey, AlarmState>,
+    alarm_payloads: &mut HashMap<AlarmKey, AlarmPayload>,
+    history: &mut History,
+    baselines: &mut Baselines,
+    mut usages: Option<&mut Vec<Usage>>,
+    samples: Vec<(Usage, Vec<Usage>)>,
+    period_secs: u32,
+) {
+    // Alarms of the other periods aren't due, they're left out of the evaluation
+    let (mut due, others) = std::mem::take(&mut *alarm_payloads)
+        .into_iter()
+        .partition::<HashMap<_, _>, _>(|(_, payload)| {
+            payload.config().period_secs() == period_secs
+        });
+
+    for (usage, replicas) in samples {
+        if let Some(usages) = usages.as_deref_mut() {
+            history.push(usage.clone());
+            usages.push(usage.clone());
+            usages.extend(replicas.iter().cloned());
+        }
+
+        let sample_secs = usage.secs() as u32;
+        process_usage(
+            alarms,
+            &mut due,
+            history,
+            baselines,
+            usage.clone(),
+            replicas,
+            sample_secs,
+        );
+
+        if usages.is_some() {
+            learn(baselines, due.iter().chain(&others), &usage);
+        }
+    }
+
+    *alarm_payloads = others;
+    alarm_payloads.extend(due);
+}
+
+/// Learns the usage of the service for the anomaly alarms, once for each variable however many alarms monitor it.
+/// Called after evaluating, so an anomaly isn't part of the band it's compared against
+pub fn learn<'a>(
+    baselines: &mut Baselines,
+    alarm_payloads: impl IntoIterator<Item = (&'a AlarmKey, &'a AlarmPayload)>,
+    usage: &Usage,
+) {
+    let mut variables = alarm_payloads
+        .into_iter()
+        .filter(|(key, _)| key.alarm() == Alarm::Anomaly)
+        .map(|(key, payload)| payload.config().anomaly_variable(key))
+        .collect::<Vec<_>>();
+    variables.sort();
+    variables.dedup();
+
+    for variable in variables {
+        if let Some(measured) = usage.rate(&variable) {
+            baselines.learn(&variable, usage.end_date(), measured);
+        }
+    }
+}
+
+pub fn process_usage(
+    alarms: &mut HashMap<AlarmKey, AlarmState>,
+    alarm_payloads: &mut HashMap<AlarmKey, AlarmPayload>,
+    history: &History,
+    baselines: &Baselines,
     usage: Usage,
-    period_secs: u16,
+    replicas: Vec<Usage>,
+    period_secs: u32,
 ) {
-    for (alarm, payload) in alarm_payloads {
+    let replica_ids = replicas
+        .iter()
+        .filter_map(|usage| usage.replica().clone())
+        .collect::<Vec<_>>();
+    sync_replicas(
+        alarms,
+        alarm_payloads,
+        |alarm| {
+            alarm != Alarm::HealthCheckFailed
+                && alarm != Alarm::Composite
+                && alarm != Alarm::Anomaly
+                && !alarm.is_trend()
+                && !is_deployment_alarm(alarm)
+        },
+        &replica_ids,
+    );
+
+    for (key, payload) in alarm_payloads {
         enum Ordering {
             Less,
             Greater,
         }
 
-        let (measured, ordering) = match alarm {
-            Alarm::CpuLowerLimitVcpus => (usage.cpu(), Ordering::Less),
-            Alarm::CpuUpperLimitVcpus => (usage.cpu(), Ordering::Greater),
+        // Alarms configured per replica only evaluate the usage of each replica
+        let usage = match key.replica() {
+            None if payload.config().per_replica() => continue,
+            None => &usage,
+            Some(replica) => match replicas
+                .iter()
+                .find(|usage| usage.replica().as_ref() == Some(replica))
+            {
+                Some(usage) => usage,
+                None => continue,
+            },
+        };
+
+        if key.alarm() == Alarm::Expression {
+            process_expression(alarms, key, payload, usage, period_secs);
+            continue;
+        }
+
+        if key.alarm() == Alarm::Anomaly {
+            process_anomaly(alarms, key, payload, baselines, usage, period_secs);
+            continue;
+        }
+
+        if key.alarm().is_trend() {
+            process_trend(alarms, key, payload, history, period_secs);
+            continue;
+        }
+
+        let (measured, ordering) = match key.alarm() {
+            Alarm::CpuLowerLimitVcpus | Alarm::CpuLowerLimitPercent => {
+                (usage.cpu(), Ordering::Less)
+            }
+            Alarm::CpuUpperLimitVcpus | Alarm::CpuUpperLimitPercent => {
+                (usage.cpu(), Ordering::Greater)
+            }
             Alarm::DiskLowerLimitGb => (usage.disk_gb(), Ordering::Less),
             Alarm::DiskUpperLimitGb => (usage.disk_gb(), Ordering::Greater),
             Alarm::EgressLowerLimitGb => (usage.egress_gb(), Ordering::Less),
             Alarm::EgressUpperLimitGb => (usage.egress_gb(), Ordering::Greater),
             Alarm::IngressLowerLimitGb => (usage.ingress_gb(), Ordering::Less),
             Alarm::IngressUpperLimitGb => (usage.ingress_gb(), Ordering::Greater),
-            Alarm::MemoryLowerLimitGb => (usage.memory_gb(), Ordering::Less),
-            Alarm::MemoryUpperLimitGb => (usage.memory_gb(), Ordering::Greater),
+            Alarm::MemoryLowerLimitGb | Alarm::MemoryLowerLimitPercent => {
+                (usage.memory_gb(), Ordering::Less)
+            }
+            Alarm::MemoryUpperLimitGb | Alarm::MemoryUpperLimitPercent => {
+                (usage.memory_gb(), Ordering::Greater)
+            }
             Alarm::CostUpperLimit => todo!(),
             // Processed elsewhere
-            Alarm::HealthCheckFailed => continue,
+            Alarm::HealthCheckFailed
+            | Alarm::Anomaly
+            | Alarm::Composite
+            | Alarm::DiskForecastUpperLimitGb
+            | Alarm::DiskGrowthUpperLimitGbPerHour
+            | Alarm::MemoryForecastUpperLimitGb
+            | Alarm::MemoryGrowthUpperLimitGbPerHour
+            | Alarm::Expression
+            | Alarm::LogMatched
+            | Alarm::DeploymentCrashed
+            | Alarm::DeploymentFailed
+            | Alarm::DeploymentRemoved
+            | Alarm::DeploymentRestartLoop => continue,
+        };
+        // Point statistics pick one of the samples, so they are kept per minute to be compared to the value itself
+        let measured = match usage.secs() {
+            secs if payload.config().statistic().is_point() && secs > 0. => measured * 60. / secs,
+            _ => measured,
         };
-        payload.accumulated += measured;
-        payload.minutes += period_secs / 60;
+        payload.samples.push(measured);
+        payload.secs += period_secs;
 
-        if payload.minutes() >= payload.config().period_minutes() {
-            let average_measured = payload.accumulated() / f64::from(payload.minutes());
+        if payload.secs() >= payload.config().period_secs() {
+            let statistic = payload.config().statistic();
             let config_value: f64 = match payload.config().value().parse() {
                 Ok(value) => value,
                 Err(err) => {
-                    error!("Should never happen: invalid float in {alarm} ({err})");
+                    error!("Should never happen: invalid float in {key} ({err})");
                     0.
                 }
             };
-            if config_value != 0. {
-                let average_threshold = config_value / f64::from(payload.config().period_minutes());
 
-                let alarming = match ordering {
-                    Ordering::Less => average_measured < average_threshold,
-                    Ordering::Greater => average_measured > average_threshold,
-                };
+            let limit = match key.alarm() {
+                Alarm::CpuLowerLimitPercent | Alarm::CpuUpperLimitPercent => {
+                    usage.cpu_limit_vcpus()
+                }
+                Alarm::MemoryLowerLimitPercent | Alarm::MemoryUpperLimitPercent => {
+                    usage.memory_limit_gb()
+                }
+                _ => None,
+            };
 
-                // Keep only the needed measurements
-                payload.measurements.push_back(alarming);
-                if payload.measurements().len() > payload.config().data_points().into() {
-                    let _ = payload.measurements.pop_front();
+            // The thresholds are for the whole period, so the sum is compared to them directly and the
+            // average to the share of the threshold of a sample. Point statistics are per minute, so they are
+            // compared to the value itself. Percentages are of the current limit, which applies to each minute
+            let period_minutes = f64::from(payload.config().period_secs()) / 60.;
+            let sample_minutes = usage.secs() / 60.;
+            let to_threshold = |value: f64| match (key.alarm().is_percentage(), statistic) {
+                (true, Statistic::Sum) => limit.map(|limit| value / 100. * limit * period_minutes),
+                (true, Statistic::Average) => {
+                    limit.map(|limit| value / 100. * limit * sample_minutes)
                 }
+                (true, _) => limit.map(|limit| value / 100. * limit),
+                (false, Statistic::Average) => Some(value * sample_minutes / period_minutes),
+                (false, _) => Some(value),
+            };
 
-                // Emit alarm if enough data points alarmed
-                if payload.measurements().iter().filter(|a| **a).count()
-                    >= payload.config().data_points_to_alarm().into()
-                {
-                    if !payload.state() {
-                        payload.state = true;
-                        alarms.insert(*alarm, AlarmState::new(*alarm, payload.state()));
+            if config_value != 0. {
+                match to_threshold(config_value) {
+                    Some(threshold) => {
+                        // A sample was just collected, so there is always a statistic
+                        let measured = statistic.compute(payload.samples()).unwrap_or(measured);
+
+                        let alarming = match ordering {
+                            Ordering::Less => measured < threshold,
+                            Ordering::Greater => measured > threshold,
+                        };
+                        let recovered = match payload.config().recovery().and_then(to_threshold) {
+                            Some(recovery) => match ordering {
+                                Ordering::Less => measured >= recovery,
+                                Ordering::Greater => measured <= recovery,
+                            },
+                            None => !alarming,
+                        };
+
+                        record_with_recovery(
+                            alarms,
+                            key,
+                            payload,
+                            alarming,
+                            recovered,
+                            Some(measured),
+                        );
                     }
-                } else if payload.state() {
-                    payload.state = false;
-                    alarms.insert(*alarm, AlarmState::new(*alarm, payload.state()));
+                    None => warn!("Limits of the service are unknown, skipping {key}"),
                 }
             }
 
-            payload.accumulated = 0.;
-            payload.minutes = 0;
+            payload.samples.clear();
+            payload.secs = 0;
+        }
+    }
+}
+
+fn process_expression(
+    alarms: &mut HashMap<AlarmKey, AlarmState>,
+    key: &AlarmKey,
+    payload: &mut AlarmPayload,
+    usage: &Usage,
+    period_secs: u32,
+) {
+    payload.usages.push(usage.clone());
+    payload.secs += period_secs;
+
+    if payload.secs() >= payload.config().period_secs() {
+        match Expression::parse(payload.config().value(), &Usage::VARIABLES) {
+            Ok(expression) => {
+                // All variables share the period and statistic of the alarm
+                let statistic = payload.config().statistic();
+                let value = expression.evaluate(&|name| {
+                    let samples = payload
+                        .usages()
+                        .iter()
+                        .filter_map(|usage| usage.rate(name))
+                        .collect::<Vec<_>>();
+                    statistic.compute(&samples)
+                });
+
+                match value {
+                    Some(value) => record(alarms, key, payload, value != 0., Some(value)),
+                    None => error!("Should never happen: missing variable in {key}"),
+                }
+            }
+            Err(err) => error!("Should never happen: invalid expression in {key} ({err})"),
+        }
+
+        payload.usages.clear();
+        payload.secs = 0;
+    }
+}
+
+/// Anomaly alarms are evaluated for the whole service, against the band learned for the current hour of the week
+fn process_anomaly(
+    alarms: &mut HashMap<AlarmKey, AlarmState>,
+    key: &AlarmKey,
+    payload: &mut AlarmPayload,
+    baselines: &Baselines,
+    usage: &Usage,
+    period_secs: u32,
+) {
+    let variable = payload.config().anomaly_variable(key);
+    let Some(measured) = usage.rate(&variable) else {
+        warn!("Unable to measure {variable}, skipping {key}");
+        return;
+    };
+    payload.samples.push(measured);
+    payload.secs += period_secs;
+
+    if payload.secs() >= payload.config().period_secs() {
+        let deviations: f64 = match payload.config().value().parse() {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Should never happen: invalid float in {key} ({err})");
+                0.
+            }
+        };
+
+        if deviations != 0. {
+            match baselines.band(&variable, usage.end_date()) {
+                Some(band) => {
+                    // Bands are learned per minute, so the sum is compared per sample
+                    let statistic = payload.config().statistic();
+                    let measured = statistic.compute(payload.samples()).unwrap_or(measured);
+                    let measured = match statistic {
+                        Statistic::Sum => measured / payload.samples().len() as f64,
+                        _ => measured,
+                    };
+
+                    let measured = band.deviations(measured);
+                    let recovered = match payload.config().recovery() {
+                        Some(recovery) => measured <= recovery,
+                        None => measured <= deviations,
+                    };
+                    record_with_recovery(
+                        alarms,
+                        key,
+                        payload,
+                        measured > deviations,
+                        recovered,
+                        Some(measured),
+                    );
+                }
+                None => debug!("Still learning the baseline of {variable}, skipping {key}"),
+            }
+        }
+
+        payload.samples.clear();
+        payload.secs = 0;
+    }
+}
+
+/// Trend alarms are evaluated for the whole service, from the line fitted to its retained history
+fn process_trend(
+    alarms: &mut HashMap<AlarmKey, AlarmState>,
+    key: &AlarmKey,
+    payload: &mut AlarmPayload,
+    history: &History,
+    period_secs: u32,
+) {
+    payload.secs += period_secs;
+
+    if payload.secs() >= payload.config().period_secs() {
+        payload.secs = 0;
+
+        let config_value: f64 = match payload.config().value().parse() {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Should never happen: invalid float in {key} ({err})");
+                0.
+            }
+        };
+        if config_value == 0. {
+            return;
+        }
+
+        let variable = match key.alarm() {
+            Alarm::DiskForecastUpperLimitGb | Alarm::DiskGrowthUpperLimitGbPerHour => "disk_gb",
+            _ => "memory_gb",
+        };
+        let Some(trend) = history.trend(variable, payload.config().window_minutes()) else {
+            debug!("Not enough history to evaluate {key}");
+            return;
+        };
+
+        let measured = match key.alarm() {
+            Alarm::DiskGrowthUpperLimitGbPerHour | Alarm::MemoryGrowthUpperLimitGbPerHour => {
+                trend.per_hour()
+            }
+            _ => trend.forecast(payload.config().horizon_hours()),
+        };
+        let recovery = payload.config().recovery().unwrap_or(config_value);
+        record_with_recovery(
+            alarms,
+            key,
+            payload,
+            measured > config_value,
+            measured <= recovery,
+            Some(measured),
+        );
+    }
+}
+
+/// Composite alarms follow the state of the alarms they combine, without a window of data points
+/// Composites are evaluated from the latest state of every other alarm, whichever task evaluated it
+/// Returns the variables of the alarms suppressed by a composite in the ALARM state
+pub fn composites(
+    alarms: &mut HashMap<AlarmKey, AlarmState>,
+    composite_payloads: &mut HashMap<AlarmKey, AlarmPayload>,
+    alarm_states: &HashMap<AlarmKey, bool>,
+) -> Vec<String> {
+    // Alarms evaluated per replica are on if any of the replicas is on
+    let mut states: HashMap<String, bool> = HashMap::new();
+    for (key, state) in alarm_states {
+        if key.alarm() != Alarm::Composite {
+            *states.entry(key.variable()).or_default() |= state;
+        }
+    }
+    let variables = states.keys().map(String::as_str).collect::<Vec<_>>();
+
+    let mut suppressed = Vec::new();
+    for (key, payload) in composite_payloads.iter_mut() {
+        if key.alarm() != Alarm::Composite {
+            continue;
+        }
+
+        let expression = match Expression::parse(payload.config().value(), &variables) {
+            Ok(expression) => expression,
+            Err(err) => {
+                error!("Should never happen: invalid composite in {key} ({err})");
+                continue;
+            }
+        };
+
+        let on = expression
+            .evaluate(&|name| states.get(name).map(|on| if *on { 1. } else { 0. }))
+            .is_some_and(|value| value != 0.);
+        if on != payload.state() {
+            payload.state = on;
+            alarms.insert(key.clone(), AlarmState::new(key.clone(), on));
+        }
+
+        if payload.state() && payload.config().suppress() {
+            suppressed.extend(expression.variables().into_iter().map(ToOwned::to_owned));
+        }
+    }
+
+    // Resolutions are still sent so no incident is left open for an alarm triggered before the composite
+    alarms.retain(|key, state| !state.on() || !suppressed.contains(&key.variable()));
+    suppressed
+}
+
+/// Percentage thresholds and expressions referring to the limits require them to be fetched
+pub fn needs_limits(key: &AlarmKey, config: &AlarmConfig) -> bool {
+    match key.alarm() {
+        Alarm::Expression => Expression::parse(config.value(), &Usage::VARIABLES)
+            .map(|expression| {
+                expression
+                    .variables()
+                    .iter()
+                    .any(|variable| matches!(*variable, "cpu_limit_vcpus" | "memory_limit_gb"))
+            })
+            .unwrap_or(false),
+        alarm => alarm.is_percentage(),
+    }
+}
+
+/// Alarms evaluated from the latest deployment, they can't be evaluated per replica
+pub fn is_deployment_alarm(alarm: Alarm) -> bool {
+    deployment_status(alarm).is_some()
+        || alarm == Alarm::DeploymentRestartLoop
+        || alarm == Alarm::LogMatched
+}
+
+/// Status of the deployment that breaches the alarm, if it's a deployment status alarm
+fn deployment_status(alarm: Alarm) -> Option<DeploymentStatus> {
+    match alarm {
+        Alarm::DeploymentCrashed => Some(DeploymentStatus::Crashed),
+        Alarm::DeploymentFailed => Some(DeploymentStatus::Failed),
+        Alarm::DeploymentRemoved => Some(DeploymentStatus::Removed),
+        _ => None,
+    }
+}
+
+fn process_deployment(
+    alarms: &mut HashMap<AlarmKey, AlarmState>,
+    alarm_payloads: &mut HashMap<AlarmKey, AlarmPayload>,
+    deployment: Option<Deployment>,
+    period_secs: u32,
+) {
+    let (status, restarts) = match &deployment {
+        Some(deployment) => (Some(deployment.status()), deployment.restarts()),
+        None => (None, 0),
+    };
+
+    for (key, payload) in alarm_payloads {
+        if key.alarm() == Alarm::DeploymentRestartLoop {
+            payload.samples.push(f64::from(restarts));
+        } else if deployment_status(key.alarm()).is_none() {
+            continue;
+        }
+        payload.secs += period_secs;
+
+        if payload.secs() >= payload.config().period_secs() {
+            let (alarming, measurement) = if key.alarm() == Alarm::DeploymentRestartLoop {
+                let max_restarts: f64 = match payload.config().value().parse() {
+                    Ok(value) => value,
+                    Err(err) => {
+                        error!(
+                            "Should never happen: invalid float in {} ({err})",
+                            key.alarm()
+                        );
+                        0.
+                    }
+                };
+                let restarts = Statistic::Sum.compute(payload.samples()).unwrap_or(0.);
+                (
+                    max_restarts != 0. && restarts > max_restarts,
+                    Some(restarts),
+                )
+            } else {
+                let alarming = status.is_some() && status == deployment_status(key.alarm());
+                (alarming, None)
+            };
+
+            record(alarms, key, payload, alarming, measurement);
+
+            payload.samples.clear();
+            payload.secs = 0;
+        }
+    }
+}
+
+fn process_logs(
+    alarms: &mut HashMap<AlarmKey, AlarmState>,
+    alarm_payloads: &mut HashMap<AlarmKey, AlarmPayload>,
+    logs: Vec<Log>,
+    period_secs: u32,
+) {
+    for (key, payload) in alarm_payloads {
+        if key.alarm() != Alarm::LogMatched {
+            continue;
+        }
+
+        let pattern = match Regex::new(payload.config().value()) {
+            Ok(pattern) => pattern,
+            Err(err) => {
+                error!("Should never happen: invalid regex in {key} ({err})");
+                continue;
+            }
+        };
+        let matched = logs
+            .iter()
+            .filter(|log| pattern.is_match(log.message()))
+            .count();
+        payload.samples.push(matched as f64);
+        payload.secs += period_secs;
+
+        if payload.secs() >= payload.config().period_secs() {
+            let matched = Statistic::Sum.compute(payload.samples()).unwrap_or(0.);
+            let alarming = matched > f64::from(payload.config().max_lines());
+            record(alarms, key, payload, alarming, Some(matched));
+
+            payload.samples.clear();
+            payload.secs = 0;
         }
     }
 }
 
-async fn healthcheck(url: &str) -> bool {
-    match reqwest::Client::new()
-        .get(url)
-        .fetch_mode_no_cors()
-        .send()
-        .await
+/// Keeps one payload per replica for the alarms configured per replica, replicas that stopped existing are resolved
+fn sync_replicas(
+    alarms: &mut HashMap<AlarmKey, AlarmState>,
+    alarm_payloads: &mut HashMap<AlarmKey, AlarmPayload>,
+    filter: impl Fn(Alarm) -> bool,
+    replicas: &[String],
+) {
+    let configs = alarm_payloads
+        .iter()
+        .filter(|(key, payload)| {
+            key.replica().is_none() && payload.config().per_replica() && filter(key.alarm())
+        })
+        .map(|(key, payload)| (key.clone(), payload.config().clone()))
+        .collect::<Vec<_>>();
+
+    for (base, config) in configs {
+        for replica in replicas {
+            alarm_payloads
+                .entry(base.with_replica(replica.clone()))
+                .or_insert_with(|| AlarmPayload::from_config(config.clone()));
+        }
+
+        alarm_payloads.retain(|key, payload| {
+            let gone = key.alarm() == base.alarm()
+                && key.name() == base.name()
+                && key
+                    .replica()
+                    .as_ref()
+                    .is_some_and(|replica| !replicas.contains(replica));
+            if gone && payload.state() {
+                alarms.insert(key.clone(), AlarmState::new(key.clone(), false));
+            }
+            !gone
+        });
+    }
+}
+
+/// Stores the data point and emits the alarm if it changed state
+fn record(
+    alarms: &mut HashMap<AlarmKey, AlarmState>,
+    key: &AlarmKey,
+    payload: &mut AlarmPayload,
+    alarming: bool,
+    measurement: Option<f64>,
+) {
+    record_with_recovery(alarms, key, payload, alarming, !alarming, measurement);
+}
+
+/// Alarms whose recovery threshold differs from the one that breaches only resolve once it's crossed back
+fn record_with_recovery(
+    alarms: &mut HashMap<AlarmKey, AlarmState>,
+    key: &AlarmKey,
+    payload: &mut AlarmPayload,
+    alarming: bool,
+    recovered: bool,
+    measurement: Option<f64>,
+) {
+    let data_points = payload.config().data_points().into();
+    let data_points_to_alarm = payload.config().data_points_to_alarm().into();
+    let recovery_data_points = payload.config().recovery_data_points().map(usize::from);
+
+    // Keep only the needed measurements
+    keep(&mut payload.measurements, alarming, data_points);
+    keep(
+        &mut payload.recoveries,
+        recovered,
+        recovery_data_points.unwrap_or(data_points),
+    );
+
+    let mut state = if payload.state() {
+        let resolved = match recovery_data_points {
+            Some(recovery_data_points) => {
+                payload.recoveries().len() >= recovery_data_points
+                    && payload.recoveries().iter().all(|r| *r)
+            }
+            None => payload.recoveries().iter().filter(|r| !**r).count() < data_points_to_alarm,
+        };
+        !resolved
+    } else {
+        // Emit alarm if enough data points alarmed
+        payload.measurements().iter().filter(|a| **a).count() >= data_points_to_alarm
+    };
+
+    let flap_limit = payload.config().flap_limit().into();
+    if payload.state()
+        && !state
+        && flap_limit > 0
+        && payload.toggles().iter().filter(|t| **t).count() > flap_limit
     {
+        info!("{key} is flapping, holding it in the ALARM state");
+        state = true;
+    }
+
+    let toggled = state != payload.state();
+    let flap_data_points = payload.config().flap_data_points().into();
+    keep(&mut payload.toggles, toggled, flap_data_points);
+
+    if toggled {
+        payload.state = state;
+        let state = AlarmState::new(key.clone(), payload.state()).with_measurement(measurement);
+        alarms.insert(key.clone(), state);
+    }
+}
+
+/// Appends the value to the window, dropping the oldest ones that don't fit
+fn keep(window: &mut VecDeque<bool>, value: bool, len: usize) {
+    window.push_back(value);
+    while window.len() > len {
+        let _ = window.pop_front();
+    }
+}
+
+/// Returns the health of each replica, or of the service if it can't be checked per replica. `replica_ids` keeps the
+/// deployment instance each address reported, so a replica that stops responding keeps its identity
+async fn healthcheck(
+    url: &str,
+    per_replica: bool,
+    replica_ids: &mut HashMap<String, String>,
+) -> Vec<(Option<String>, bool)> {
+    if per_replica {
+        match replica_urls(url).await {
+            // Nothing to probe means no replica is reachable
+            Some((_, replicas)) if replicas.is_empty() => return vec![(None, false)],
+            Some((host, replicas)) => {
+                replica_ids.retain(|address, _| replicas.iter().any(|(other, _)| address == other));
+
+                let mut health = Vec::with_capacity(replicas.len());
+                for (address, replica_url) in replicas {
+                    let (healthy, reported) = probe(replica_url.as_str(), Some(&host)).await;
+                    let replica = identify(replica_ids, address, reported);
+                    health.push((Some(replica), healthy));
+                }
+                return health;
+            }
+            None => warn!("Unable to check each replica of {url}, only private domains ({PRIVATE_DOMAIN_SUFFIX}) are supported"),
+        }
+    }
+
+    vec![(None, probe(url, None).await.0)]
+}
+
+/// Replicas are identified by the deployment instance they reported last, by their address if they never did
+fn identify(
+    replica_ids: &mut HashMap<String, String>,
+    address: String,
+    reported: Option<String>,
+) -> String {
+    match reported {
+        Some(id) => {
+            replica_ids.insert(address, id.clone());
+            id
+        }
+        None => replica_ids.get(&address).cloned().unwrap_or(address),
+    }
+}
+
+/// Resolves each replica behind a private domain, public domains are load balanced so replicas can't be targeted
+///
+/// Returns the original host, to be sent in the `Host` header, and the url of each replica
+async fn replica_urls(url: &str) -> Option<(String, Vec<(String, reqwest::Url)>)> {
+    let parsed = reqwest::Url::parse(url).ok()?;
+    let host = parsed.host_str()?;
+    if !host.ends_with(PRIVATE_DOMAIN_SUFFIX) {
+        return None;
+    }
+
+    let port = parsed.port_or_known_default()?;
+    let host_header = match parsed.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_owned(),
+    };
+
+    let addresses = match tokio::net::lookup_host((host, port)).await {
+        Ok(addresses) => addresses,
+        Err(err) => {
+            debug!("Unable to resolve {host}: {err}");
+            return Some((host_header, Vec::new()));
+        }
+    };
+
+    let mut replicas = Vec::new();
+    for address in addresses {
+        let mut replica_url = parsed.clone();
+        if replica_url.set_ip_host(address.ip()).is_ok() {
+            replicas.push((address.ip().to_string(), replica_url));
+        }
+    }
+    Some((host_header, replicas))
+}
+
+/// Returns whether the endpoint is healthy and the replica it reported, if any
+async fn probe(url: &str, host: Option<&str>) -> (bool, Option<String>) {
+    let mut request = reqwest::Client::new().get(url).fetch_mode_no_cors();
+    if let Some(host) = host {
+        request = request.header("Host", host);
+    }
+
+    match request.send().await {
         Ok(response) => {
             debug!("Healthcheck {url} status {}", response.status());
-            response.status() == 200
+            let replica = response
+                .headers()
+                .get(REPLICA_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned);
+            (response.status() == 200, replica)
         }
         Err(err) => {
             debug!("Healthcheck {url} request failed: {err}");
-            false
+            (false, None)
         }
     }
 }
 
-fn process_healthcheck(
-    alarms: &mut HashMap<Alarm, AlarmState>,
-    payload: &mut AlarmPayload,
-    is_on: bool,
+pub fn process_healthcheck(
+    alarms: &mut HashMap<AlarmKey, AlarmState>,
+    alarm_payloads: &mut HashMap<AlarmKey, AlarmPayload>,
+    replicas: Vec<(Option<String>, bool)>,
 ) {
-    // Keep only the needed measurements
-    payload.measurements.push_back(!is_on);
-    if payload.measurements().len() > payload.config().data_points().into() {
-        let _ = payload.measurements.pop_front();
+    let replica_ids = replicas
+        .iter()
+        .filter_map(|(replica, _)| replica.clone())
+        .collect::<Vec<_>>();
+    sync_replicas(
+        alarms,
+        alarm_payloads,
+        |alarm| alarm == Alarm::HealthCheckFailed,
+        &replica_ids,
+    );
+
+    for (replica, is_on) in replicas {
+        let key = AlarmKey::new(Alarm::HealthCheckFailed, None, replica);
+        if let Some(payload) = alarm_payloads.get_mut(&key) {
+            record(alarms, &key, payload, !is_on, None);
+        }
     }
+}
 
-    // Emit alarm if enough data points alarmed
-    if payload.measurements().iter().filter(|a| **a).count()
-        >= payload.config().data_points_to_alarm().into()
-    {
-        if !payload.state() {
-            payload.state = true;
-            alarms.insert(
-                Alarm::HealthCheckFailed,
-                AlarmState::new(Alarm::HealthCheckFailed, payload.state()),
+#[cfg(test)]
+mod tests {
+    use super::{
+        composites, identify, process_healthcheck, process_usage, record_with_recovery,
+        usage_alarms, AlarmPayload,
+    };
+    use crate::{
+        scheduler::{Context, SourceState},
+        Alarm, AlarmConfig, AlarmKey, AlarmState, Baselines, FakeClock, FakeRailway, History,
+        RailwayClient, Statistic, Usage,
+    };
+    use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+    use std::{collections::HashMap, sync::Arc};
+    use tokio_util::sync::CancellationToken;
+
+    fn usage(minute: i64, cpu: f64, replica: Option<&str>) -> Usage {
+        let end_date =
+            Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap() + TimeDelta::minutes(minute);
+        serde_json::from_value(serde_json::json!({
+            "cpu": cpu,
+            "memoryGb": 0.,
+            "diskGb": 0.,
+            "ingressGb": 0.,
+            "egressGb": 0.,
+            "startDate": end_date - TimeDelta::minutes(1),
+            "endDate": end_date,
+            "replica": replica,
+        }))
+        .expect("invalid usage")
+    }
+
+    /// Metrics of the CPU of the whole service, one value per minute from `start`
+    fn metrics(start: DateTime<Utc>, cpus: &[f64]) -> serde_json::Value {
+        let values = cpus
+            .iter()
+            .enumerate()
+            .map(|(minute, cpu)| {
+                let date = start + TimeDelta::minutes(minute as i64);
+                serde_json::json!({ "ts": date.timestamp(), "value": cpu })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "metrics": [{
+                "measurement": "CPU_USAGE",
+                "tags": { "deploymentInstanceId": null },
+                "values": values,
+            }],
+        })
+    }
+
+    fn context(railway: &Arc<FakeRailway>, start: DateTime<Utc>) -> Context {
+        Context {
+            clock: Arc::new(FakeClock::new(start)),
+            railway: RailwayClient::with_transport(railway.clone()),
+            project_id: "project".to_owned(),
+            service_id: "service".to_owned(),
+        }
+    }
+
+    fn source_state() -> SourceState {
+        SourceState {
+            history: History::new(0),
+            baselines: Baselines::default(),
+            unmeasured_since: HashMap::new(),
+            replica_ids: HashMap::new(),
+        }
+    }
+
+    fn payloads(key: AlarmKey, config: AlarmConfig) -> HashMap<AlarmKey, AlarmPayload> {
+        HashMap::from([(key, AlarmPayload::from_config(config))])
+    }
+
+    #[test]
+    fn per_replica() {
+        let config = AlarmConfig {
+            value: "1".to_owned(),
+            data_points: 1,
+            data_points_to_alarm: 1,
+            per_replica: true,
+            ..AlarmConfig::default()
+        };
+        let cpu = AlarmKey::from(Alarm::CpuUpperLimitVcpus);
+        let mut alarm_payloads = payloads(cpu.clone(), config);
+        let (history, baselines) = (History::new(0), Baselines::default());
+        let mut evaluate = |minute, replicas: &[(&str, f64)]| {
+            let mut alarms = HashMap::new();
+            let replicas = replicas
+                .iter()
+                .map(|(replica, cpu)| usage(minute, *cpu, Some(replica)))
+                .collect();
+            let service = usage(minute, 0., None);
+            process_usage(
+                &mut alarms,
+                &mut alarm_payloads,
+                &history,
+                &baselines,
+                service,
+                replicas,
+                60,
+            );
+            alarms
+        };
+
+        // Only the replica above the limit breaches
+        let alarms = evaluate(1, &[("a", 2.), ("b", 0.5)]);
+        assert_eq!(alarms.len(), 1);
+        assert!(alarms[&cpu.with_replica("a".to_owned())].on());
+
+        // A replica that stopped existing is resolved, a new one starts from scratch
+        let alarms = evaluate(2, &[("b", 0.5), ("c", 2.)]);
+        assert_eq!(alarms.len(), 2);
+        assert!(!alarms[&cpu.with_replica("a".to_owned())].on());
+        assert!(alarms[&cpu.with_replica("c".to_owned())].on());
+        let mut replicas = alarm_payloads
+            .keys()
+            .filter_map(|key| key.replica().clone())
+            .collect::<Vec<_>>();
+        replicas.sort();
+        assert_eq!(replicas, ["b", "c"]);
+    }
+
+    /// State of the alarm after a period of one sample per minute
+    fn evaluate(alarm: Alarm, statistic: Statistic, value: &str, samples: &[f64]) -> bool {
+        let config = AlarmConfig {
+            value: value.to_owned(),
+            period_secs: 60 * samples.len() as u32,
+            data_points: 1,
+            data_points_to_alarm: 1,
+            statistic,
+            ..AlarmConfig::default()
+        };
+        let key = AlarmKey::from(alarm);
+        let mut alarm_payloads = payloads(key.clone(), config);
+        let (history, baselines) = (History::new(0), Baselines::default());
+        for (minute, cpu) in samples.iter().enumerate() {
+            process_usage(
+                &mut HashMap::new(),
+                &mut alarm_payloads,
+                &history,
+                &baselines,
+                usage(minute as i64, *cpu, None),
+                Vec::new(),
+                60,
             );
         }
-    } else if payload.state() {
-        payload.state = false;
-        alarms.insert(
-            Alarm::HealthCheckFailed,
-            AlarmState::new(Alarm::HealthCheckFailed, payload.state()),
+        alarm_payloads[&key].state()
+    }
+
+    #[test]
+    fn statistics() {
+        let upper = Alarm::CpuUpperLimitVcpus;
+        let spike = [1., 1., 1., 1., 3.];
+
+        // Point statistics compare a single minute to the threshold, not its share of the period
+        assert!(evaluate(upper, Statistic::Maximum, "2", &spike));
+        assert!(!evaluate(
+            upper,
+            Statistic::Maximum,
+            "2",
+            &[1., 1., 1., 1., 1.5]
+        ));
+        assert!(evaluate(upper, Statistic::Percentile(99.), "2", &spike));
+        assert!(!evaluate(upper, Statistic::Percentile(80.), "2", &spike));
+        assert!(evaluate(
+            Alarm::CpuLowerLimitVcpus,
+            Statistic::Minimum,
+            "0.5",
+            &[1., 1., 0.2]
+        ));
+        assert!(!evaluate(
+            Alarm::CpuLowerLimitVcpus,
+            Statistic::Minimum,
+            "0.5",
+            &[1., 1., 0.6]
+        ));
+
+        // Aggregates compare the period to the threshold of the whole period
+        assert!(evaluate(upper, Statistic::Sum, "2", &[0.5; 5]));
+        assert!(!evaluate(upper, Statistic::Sum, "2", &[0.3; 5]));
+        assert!(evaluate(upper, Statistic::Average, "2", &[0.5; 5]));
+        assert!(!evaluate(upper, Statistic::Average, "2", &[0.3; 5]));
+    }
+
+    /// The statistic is computed over the samples of each minute of the period, fetched at its end
+    #[tokio::test]
+    async fn sampled_statistics() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let railway = Arc::new(FakeRailway::new());
+        railway.respond("metrics", metrics(start, &[1., 1., 1., 1., 3.]));
+        let context = context(&railway, start);
+        let cpu = AlarmKey::from(Alarm::CpuUpperLimitVcpus);
+
+        let measured = |statistic, value: &str| {
+            let mut alarm_payloads = payloads(
+                cpu.clone(),
+                AlarmConfig {
+                    value: value.to_owned(),
+                    period_secs: 300,
+                    data_points: 1,
+                    data_points_to_alarm: 1,
+                    statistic,
+                    ..AlarmConfig::default()
+                },
+            );
+            let (context, cpu) = (&context, &cpu);
+            async move {
+                let alarms = usage_alarms(
+                    start,
+                    &mut alarm_payloads,
+                    &mut source_state(),
+                    &mut Vec::new(),
+                    &CancellationToken::new(),
+                    300,
+                    context,
+                )
+                .await
+                .expect("shut down");
+                alarms[cpu].measurement()
+            }
+        };
+
+        let maximum = measured(Statistic::Maximum, "2").await;
+        let average = measured(Statistic::Average, "5").await;
+        assert_eq!(maximum, Some(3.));
+        assert!(average.is_some_and(|average| (average - 1.4).abs() < 1e-9));
+
+        // A single query of the whole period, sampled every minute
+        let requests = railway.requests("metrics");
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0]["startDate"], serde_json::json!(start));
+        assert_eq!(
+            requests[0]["endDate"],
+            serde_json::json!(start + TimeDelta::minutes(5))
+        );
+        assert_eq!(requests[0]["sampleRateSeconds"], 60);
+    }
+
+    #[test]
+    fn healthcheck_replicas() {
+        let config = AlarmConfig {
+            value: "http://api.railway.internal/health".to_owned(),
+            data_points: 1,
+            data_points_to_alarm: 1,
+            per_replica: true,
+            ..AlarmConfig::default()
+        };
+        let health = AlarmKey::from(Alarm::HealthCheckFailed);
+        let mut alarm_payloads = payloads(health.clone(), config);
+
+        // The address of a replica that stopped responding keeps the deployment instance it reported
+        let mut replica_ids = HashMap::new();
+        let a = identify(
+            &mut replica_ids,
+            "10.0.0.1".to_owned(),
+            Some("a".to_owned()),
+        );
+        let b = identify(&mut replica_ids, "10.0.0.2".to_owned(), None);
+        assert_eq!((a.as_str(), b.as_str()), ("a", "10.0.0.2"));
+        assert_eq!(identify(&mut replica_ids, "10.0.0.1".to_owned(), None), "a");
+
+        let mut alarms = HashMap::new();
+        process_healthcheck(
+            &mut alarms,
+            &mut alarm_payloads,
+            vec![(Some("a".to_owned()), false), (Some("b".to_owned()), true)],
         );
+        assert_eq!(alarms.len(), 1);
+        assert!(alarms[&health.with_replica("a".to_owned())].on());
+
+        let mut alarms = HashMap::new();
+        process_healthcheck(
+            &mut alarms,
+            &mut alarm_payloads,
+            vec![(Some("b".to_owned()), true)],
+        );
+        assert!(!alarms[&health.with_replica("a".to_owned())].on());
+    }
+
+    #[test]
+    fn composite_suppress() {
+        let config = AlarmConfig {
+            value: "HealthCheckFailed AND CpuUpperLimitVcpus".to_owned(),
+            suppress: true,
+            ..AlarmConfig::default()
+        };
+        let composite = AlarmKey::named(Alarm::Composite, "DOWN".to_owned());
+        let mut composite_payloads = payloads(composite.clone(), config);
+        let health = AlarmKey::from(Alarm::HealthCheckFailed).with_replica("a".to_owned());
+        let cpu = AlarmKey::from(Alarm::CpuUpperLimitVcpus);
+        let memory = AlarmKey::from(Alarm::MemoryUpperLimitGb);
+        let alarm_states = HashMap::from([
+            (health.clone(), true),
+            (
+                AlarmKey::from(Alarm::HealthCheckFailed).with_replica("b".to_owned()),
+                false,
+            ),
+            (cpu.clone(), true),
+            (memory.clone(), true),
+        ]);
+
+        // The combined alarms are withheld while the composite is on, the others are still sent
+        let mut alarms = [&health, &cpu, &memory]
+            .into_iter()
+            .map(|key| (key.clone(), AlarmState::new(key.clone(), true)))
+            .collect();
+        let suppressed = composites(&mut alarms, &mut composite_payloads, &alarm_states);
+        assert!(suppressed.contains(&health.variable()) && suppressed.contains(&cpu.variable()));
+        assert!(!suppressed.contains(&memory.variable()));
+        let mut keys = alarms.keys().cloned().collect::<Vec<_>>();
+        keys.sort_by_key(ToString::to_string);
+        assert_eq!(keys, [composite, memory]);
+    }
+
+    #[test]
+    fn recovery() {
+        let config = AlarmConfig {
+            value: "80".to_owned(),
+            data_points: 1,
+            data_points_to_alarm: 1,
+            recovery: Some(60.),
+            flap_limit: 3,
+            ..AlarmConfig::default()
+        };
+        let key = AlarmKey::from(Alarm::CpuUpperLimitPercent);
+        let mut payload = AlarmPayload::from_config(config);
+        let mut record = |measured: f64| {
+            record_with_recovery(
+                &mut HashMap::new(),
+                &key,
+                &mut payload,
+                measured > 80.,
+                measured <= 60.,
+                Some(measured),
+            );
+            payload.state()
+        };
+
+        // Breaches above the threshold, stays in ALARM until it crosses back the recovery one
+        assert!(!record(70.));
+        assert!(record(90.));
+        assert!(record(70.));
+        assert!(!record(50.));
+
+        // Holds the alarm once it changed state more than the flap limit
+        assert!(record(90.));
+        assert!(!record(50.));
+        assert!(record(90.));
+        assert!(record(50.));
+    }
+
+    #[tokio::test]
+    async fn periods() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let railway = Arc::new(FakeRailway::new());
+        railway.respond("metrics", metrics(start, &[1.; 3]));
+        let context = context(&railway, start);
+        let config = |period_secs| AlarmConfig {
+            value: "100".to_owned(),
+            period_secs,
+            ..AlarmConfig::default()
+        };
+        let mut alarm_payloads = HashMap::from([
+            (
+                AlarmKey::from(Alarm::CpuUpperLimitVcpus),
+                AlarmPayload::from_config(config(60)),
+            ),
+            (
+                AlarmKey::from(Alarm::MemoryUpperLimitGb),
+                AlarmPayload::from_config(config(120)),
+            ),
+        ]);
+        let mut state = source_state();
+
+        // The windows of every period that elapsed are fetched in a single query
+        let mut usages = Vec::new();
+        for minute in 0..3 {
+            let _ = usage_alarms(
+                start + TimeDelta::minutes(minute),
+                &mut alarm_payloads,
+                &mut state,
+                &mut usages,
+                &CancellationToken::new(),
+                60,
+                &context,
+            )
+            .await;
+        }
+        let ranges = railway
+            .requests("metrics")
+            .iter()
+            .map(|variables| (variables["startDate"].clone(), variables["endDate"].clone()))
+            .collect::<Vec<_>>();
+        let range = |start_minute, end_minute| {
+            let date = |minute| serde_json::json!(start + TimeDelta::minutes(minute));
+            (date(start_minute), date(end_minute))
+        };
+        assert_eq!(ranges, [range(-2, 1), range(0, 2), range(2, 3)]);
+
+        // Only the shortest period is retained
+        assert_eq!(usages.len(), 3);
     }
 }
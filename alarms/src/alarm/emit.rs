@@ -1,41 +1,43 @@
-use crate::{Alarm, AlarmPayload, AlarmState, Error, Result};
+use crate::{ActionResult, AlarmKey, AlarmState, Error, Result};
 use hmac::{Hmac, Mac};
 use serde::Serialize;
 use std::collections::HashMap;
 use tracing::{error, info, debug};
 
 pub async fn emit(
-    mut alarms: HashMap<Alarm, AlarmState>,
-    alarm_payloads: &HashMap<Alarm, AlarmPayload>,
+    mut alarms: HashMap<AlarmKey, AlarmState>,
+    alarm_states: &HashMap<AlarmKey, bool>,
+    actions: &[ActionResult],
     auth: &str,
     service_id: &str,
 ) {
-    if alarms.is_empty() {
+    // Scaling decisions are notified even if no alarm changed state
+    if alarms.is_empty() && actions.is_empty() {
         return;
     }
 
     debug!("Alarm ({service_id}): {alarms:?}");
 
-    if let Err(err) = pager_duty(&alarms, service_id).await {
+    if let Err(err) = pager_duty(&alarms, actions, service_id).await {
         error!("Unable to send pager duty events for alarms: {err} - {alarms:#?}")
     }
 
     // Populates webhook alarms with all active alarms to allow combining them arbitrarily on the other side
-    for (alarm, payload) in &*alarm_payloads {
-        if payload.state() {
-            alarms.insert(*alarm, AlarmState::new(*alarm, payload.state()));
-        }
-    }
+    with_active(&mut alarms, alarm_states);
 
-    if let Err(err) = webhook(&alarms, auth, service_id).await {
+    if let Err(err) = webhook(&alarms, actions, auth, service_id).await {
         error!("Unable to send webhook for alarms: {err} - {alarms:#?}")
     }
 }
 
-async fn pager_duty(alarms: &HashMap<Alarm, AlarmState>, service_id: &str) -> Result<()> {
-    let url = std::env::var("PAGER_DUTY_URL")
+async fn pager_duty(
+    alarms: &HashMap<AlarmKey, AlarmState>,
+    actions: &[ActionResult],
+    service_id: &str,
+) -> Result<()> {
+    let base_url = std::env::var("PAGER_DUTY_URL")
         .unwrap_or_else(|_| "https://events.pagerduty.com".to_owned());
-    let url = format!("{url}/v2/enqueue");
+    let url = format!("{base_url}/v2/enqueue");
 
     if let (Ok(token), Ok(source), Ok(routing_key)) = (
         std::env::var("PAGER_DUTY_TOKEN"),
@@ -50,6 +52,13 @@ async fn pager_duty(alarms: &HashMap<Alarm, AlarmState>, service_id: &str) -> Re
             } else {
                 "resolve"
             };
+            let (target, dedup_key) = match state.key().replica() {
+                Some(replica) => (
+                    format!("{service_id} (replica {replica})"),
+                    format!("{service_id}-{}-{replica}", state.key()),
+                ),
+                None => (service_id.to_owned(), format!("{service_id}-{}", state.key())),
+            };
             let response = reqwest::Client::new()
                 .post(&url)
                 .header("Authorization", format!("Bearer {token}"))
@@ -61,11 +70,18 @@ async fn pager_duty(alarms: &HashMap<Alarm, AlarmState>, service_id: &str) -> Re
                         // TODO: allow customizing severity
                         "severity": "error",
                         // TODO: add more metadata about the breaching of the alarm
-                        "summary": format!("Railway Alarm {} breached for {source}: {service_id}", state.alarm()),
+                        "summary": format!("Railway Alarm {} breached for {source}: {target}", state.key()),
                         "class": state.alarm().to_string(),
+                        "custom_details": {
+                            "service_id": service_id,
+                            "replica": state.key().replica(),
+                            "actions": actions
+                                .iter()
+                                .filter(|action| action.key() == state.key())
+                                .collect::<Vec<_>>(),
+                        },
                     },
-                    // TODO: add replica metadata
-                    "dedup_key": format!("{service_id}-{}", state.alarm()),
+                    "dedup_key": dedup_key,
                     "event_action": event_action,
                 }))
                 .fetch_mode_no_cors()
@@ -84,27 +100,76 @@ async fn pager_duty(alarms: &HashMap<Alarm, AlarmState>, service_id: &str) -> Re
                 ));
             }
         }
+
+        // Actions without an incident, like autoscaling, are reported as changes to the service
+        let url = format!("{base_url}/v2/change/enqueue");
+        for action in actions
+            .iter()
+            .filter(|action| !alarms.contains_key(action.key()))
+        {
+            let response = reqwest::Client::new()
+                .post(&url)
+                .header("Authorization", format!("Bearer {token}"))
+                .header("Content-Type", "application/json")
+                .json(&serde_json::json!({
+                    "routing_key": routing_key,
+                    "payload": {
+                        "source": source,
+                        "summary": format!(
+                            "Railway Action {} on {}: {}",
+                            action.kind(),
+                            action.key(),
+                            action.message().as_deref().unwrap_or(&action.status().to_string()),
+                        ),
+                        "timestamp": action.date(),
+                        "custom_details": {
+                            "service_id": service_id,
+                            "action": action,
+                        },
+                    },
+                }))
+                .fetch_mode_no_cors()
+                .send()
+                .await
+                .map_err(|err| Error::WebHookFailure(err, url.clone()))?;
+
+            let status = response.status();
+            if status != 200 && status != 202 {
+                return Err(Error::WebHookStatusFailure(
+                    status.as_u16(),
+                    response
+                        .text()
+                        .await
+                        .map_err(|err| Error::WebHookBody(err, url))?,
+                ));
+            }
+        }
     }
 
     Ok(())
 }
 
-async fn webhook(alarms: &HashMap<Alarm, AlarmState>, auth: &str, service_id: &str) -> Result<()> {
-    #[derive(Serialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct WebHookPayload<'a> {
-        alarms: Vec<&'a AlarmState>,
-        service_id: &'a str,
+/// `alarm_states` are the alarms that can be notified, silenced and suppressed ones are left out by the caller
+fn with_active(alarms: &mut HashMap<AlarmKey, AlarmState>, alarm_states: &HashMap<AlarmKey, bool>) {
+    for (key, on) in alarm_states {
+        if *on {
+            alarms
+                .entry(key.clone())
+                .or_insert_with(|| AlarmState::new(key.clone(), *on));
+        }
     }
+}
 
+async fn webhook(
+    alarms: &HashMap<AlarmKey, AlarmState>,
+    actions: &[ActionResult],
+    auth: &str,
+    service_id: &str,
+) -> Result<()> {
     if let Ok(url) = std::env::var("WEB_HOOK_URL") {
         info!("Sending actions to webhook {url}");
 
-        let payload = WebHookPayload {
-            alarms: alarms.iter().map(|(_, v)| v).collect(),
-            service_id,
-        };
-        let body = serde_json::to_vec(&payload)?;
+        let body = webhook_body(alarms, actions, service_id)?;
         let signature = hash(auth, &body)?;
         let response = reqwest::Client::new()
             .post(&url)
@@ -130,9 +195,65 @@ async fn webhook(alarms: &HashMap<Alarm, AlarmState>, auth: &str, service_id: &s
     Ok(())
 }
 
+fn webhook_body(
+    alarms: &HashMap<AlarmKey, AlarmState>,
+    actions: &[ActionResult],
+    service_id: &str,
+) -> Result<Vec<u8>> {
+    #[derive(Serialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct WebHookPayload<'a> {
+        alarms: Vec<&'a AlarmState>,
+        actions: &'a [ActionResult],
+        service_id: &'a str,
+    }
+
+    let payload = WebHookPayload {
+        alarms: alarms.values().collect(),
+        actions,
+        service_id,
+    };
+    Ok(serde_json::to_vec(&payload)?)
+}
+
 fn hash(secret: &str, payload: &[u8]) -> Result<String> {
     let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())?;
     mac.update(payload);
     let result = mac.finalize().into_bytes();
     Ok(format!("{result:x}"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{webhook_body, with_active};
+    use crate::{Alarm, AlarmKey, SilenceRequest, Silences};
+    use chrono::{TimeZone, Utc};
+    use std::collections::HashMap;
+
+    #[test]
+    fn silenced_active_alarms() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let cpu = AlarmKey::from(Alarm::CpuUpperLimitVcpus);
+        let memory = AlarmKey::from(Alarm::MemoryUpperLimitGb);
+        let mut silences = Silences::new(Vec::new(), vec![cpu.variable(), memory.variable()]);
+        let request = SilenceRequest {
+            alarms: vec![cpu.variable()],
+            reason: None,
+            expires_at: None,
+            duration_minutes: Some(60),
+        };
+        silences.add(request, now).expect("valid silence");
+
+        let alarm_states = HashMap::from([(cpu, true), (memory.clone(), true)]);
+        let mut alarms = HashMap::new();
+        with_active(
+            &mut alarms,
+            &silences.notified(&alarm_states, "service", now),
+        );
+        let body = webhook_body(&alarms, &[], "service").expect("invalid body");
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("invalid json");
+        let alarms = body["alarms"].as_array().expect("missing alarms");
+        assert_eq!(alarms.len(), 1);
+        assert_eq!(alarms[0]["alarm"], memory.alarm().to_string());
+    }
+}
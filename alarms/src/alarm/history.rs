@@ -0,0 +1,133 @@
+use crate::Usage;
+use chrono::TimeDelta;
+use derive_get::Getters;
+use std::collections::VecDeque;
+
+/// Usage of the whole service retained across periods, oldest first
+#[derive(Getters, Clone, Debug)]
+pub struct History {
+    usages: VecDeque<Usage>,
+    #[copy]
+    retention_minutes: u32,
+}
+
+/// Line fitted to the retained samples of a variable
+#[derive(Getters, Copy, Clone, Debug, PartialEq)]
+pub struct Trend {
+    #[copy]
+    per_hour: f64,
+    /// Fitted value at the most recent sample
+    #[copy]
+    current: f64,
+}
+
+impl Trend {
+    pub fn forecast(self, hours: u32) -> f64 {
+        self.current + self.per_hour * f64::from(hours)
+    }
+}
+
+impl History {
+    /// Nothing is retained if the retention is `0`
+    pub fn new(retention_minutes: u32) -> Self {
+        Self {
+            usages: VecDeque::new(),
+            retention_minutes,
+        }
+    }
+
+    pub fn push(&mut self, usage: Usage) {
+        if self.retention_minutes == 0 {
+            return;
+        }
+
+        let oldest = usage.end_date() - TimeDelta::minutes(self.retention_minutes.into());
+        self.usages.push_back(usage);
+        while self
+            .usages
+            .front()
+            .is_some_and(|usage| usage.end_date() <= oldest)
+        {
+            self.usages.pop_front();
+        }
+    }
+
+    /// Fits a line with least squares to the variable over the last `window_minutes`, requires at least two samples
+    pub fn trend(&self, variable: &str, window_minutes: u32) -> Option<Trend> {
+        let latest = self.usages.back()?.end_date();
+        let oldest = latest - TimeDelta::minutes(window_minutes.into());
+
+        // Hours relative to the most recent sample, so the intercept is the current value
+        let points = self
+            .usages
+            .iter()
+            .filter(|usage| usage.end_date() > oldest)
+            .filter_map(|usage| {
+                let hours = (usage.end_date() - latest).num_seconds() as f64 / 3600.;
+                Some((hours, usage.rate(variable)?))
+            })
+            .collect::<Vec<_>>();
+        if points.len() < 2 {
+            return None;
+        }
+
+        let count = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / count;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / count;
+        let covariance = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum::<f64>();
+        let variance = points
+            .iter()
+            .map(|(x, _)| (x - mean_x).powi(2))
+            .sum::<f64>();
+        if variance == 0. {
+            return None;
+        }
+
+        let per_hour = covariance / variance;
+        Some(Trend {
+            per_hour,
+            current: mean_y - per_hour * mean_x,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use crate::Usage;
+
+    fn usage(minute: u32, disk_gb: f64) -> Usage {
+        serde_json::from_value(serde_json::json!({
+            "cpu": 0.,
+            "memoryGb": 0.,
+            "diskGb": disk_gb,
+            "ingressGb": 0.,
+            "egressGb": 0.,
+            "startDate": format!("2024-01-01T00:{:02}:00Z", minute - 1),
+            "endDate": format!("2024-01-01T00:{minute:02}:00Z"),
+        }))
+        .expect("invalid usage")
+    }
+
+    #[test]
+    fn trend() {
+        let mut history = History::new(30);
+        assert_eq!(history.trend("disk_gb", 30), None);
+
+        // Grows 0.1 GB per minute, 6 GB per hour
+        for minute in 1..=40 {
+            history.push(usage(minute, 10. + f64::from(minute) * 0.1));
+        }
+        assert_eq!(history.usages().len(), 30);
+
+        let trend = history.trend("disk_gb", 30).expect("missing trend");
+        assert!((trend.per_hour() - 6.).abs() < 1e-9);
+        assert!((trend.current() - 14.).abs() < 1e-9);
+        assert!((trend.forecast(24) - 158.).abs() < 1e-9);
+
+        assert_eq!(History::new(0).trend("disk_gb", 30), None);
+    }
+}
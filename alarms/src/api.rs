@@ -0,0 +1,240 @@
+use crate::{
+    ActionResult, Clock, Error, Filter, Range, Result, Silence, SilenceRequest, Silences, Store,
+    Transition, Updates, Usage,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{delete, get},
+    Json, Router,
+};
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex, PoisonError},
+};
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub silences: Arc<Mutex<Silences>>,
+    /// Latest results of the actions run by the alarms
+    pub actions: Arc<Mutex<VecDeque<ActionResult>>>,
+    pub store: Arc<Store>,
+    pub updates: Updates,
+    /// Requests must be authenticated with the `ALARM_TOKEN`
+    pub token: Arc<str>,
+    /// Read-only token accepted in the query of the stream, the `ALARM_TOKEN` also signs the webhooks so it's never
+    /// accepted in URLs, where it would end up in logs and browser histories
+    pub stream_token: Option<Arc<str>>,
+    pub clock: Arc<dyn Clock>,
+}
+
+pub fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/v1/silences", get(silences).post(create_silence))
+        .route("/v1/silences/:id", delete(delete_silence))
+        .route("/v1/actions", get(actions))
+        .route("/v1/history/transitions", get(transitions))
+        .route("/v1/history/usages", get(usages))
+        .route("/v1/stream", get(stream))
+        .with_state(state)
+}
+
+pub async fn serve(app: Router, port: u16, shutdown: CancellationToken) -> Result<()> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|err| Error::IoWithMetadata(err, addr.to_string()))?;
+    info!("Listening: {addr}");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+        .map_err(|err| Error::IoWithMetadata(err, addr.to_string()))
+}
+
+fn authorize(state: &ApiState, headers: &HeaderMap) -> Result<()> {
+    let token = headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(Error::AuthorizationMissing)?;
+    if !matches(token, &state.token) {
+        return Err(Error::AuthorizationMissing);
+    }
+    Ok(())
+}
+
+/// Compares every byte, so the time taken doesn't reveal how much of the token matched
+fn matches(token: &str, expected: &str) -> bool {
+    token.len() == expected.len()
+        && token
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (left, right)| diff | (left ^ right))
+            == 0
+}
+
+async fn silences(State(state): State<ApiState>, headers: HeaderMap) -> Result<Json<Vec<Silence>>> {
+    authorize(&state, &headers)?;
+
+    let silences = state
+        .silences
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .silences(state.clock.now());
+    Ok(Json(silences))
+}
+
+async fn create_silence(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Json(request): Json<SilenceRequest>,
+) -> Result<(StatusCode, Json<Silence>)> {
+    authorize(&state, &headers)?;
+
+    let silence = state
+        .silences
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .add(request, state.clock.now())?;
+    Ok((StatusCode::CREATED, Json(silence)))
+}
+
+async fn delete_silence(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Result<StatusCode> {
+    authorize(&state, &headers)?;
+
+    if state
+        .silences
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(id)
+    {
+        info!("Silence {id} removed");
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(Error::SilenceNotFound(id))
+    }
+}
+
+async fn actions(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ActionResult>>> {
+    authorize(&state, &headers)?;
+
+    let actions = state
+        .actions
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .rev()
+        .cloned()
+        .collect();
+    Ok(Json(actions))
+}
+
+async fn transitions(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Query(range): Query<Range>,
+) -> Result<Json<Vec<Transition>>> {
+    authorize(&state, &headers)?;
+
+    Ok(Json(state.store.transitions(&range)))
+}
+
+async fn usages(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Query(range): Query<Range>,
+) -> Result<Json<Vec<Usage>>> {
+    authorize(&state, &headers)?;
+
+    Ok(Json(state.store.usages(&range)))
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamQuery {
+    /// Browsers can't set headers on an `EventSource`, so the `STREAM_TOKEN` may be sent in the query instead
+    token: Option<String>,
+    #[serde(flatten)]
+    filter: Filter,
+}
+
+async fn stream(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Query(query): Query<StreamQuery>,
+) -> Result<Sse<impl Stream<Item = std::result::Result<Event, Infallible>>>> {
+    match query.token.as_deref() {
+        Some(token)
+            if state
+                .stream_token
+                .as_deref()
+                .is_some_and(|expected| matches(token, expected)) => {}
+        Some(_) => return Err(Error::AuthorizationMissing),
+        None => authorize(&state, &headers)?,
+    }
+
+    let updates =
+        state.updates.subscribe(query.filter).filter_map(|update| {
+            match Event::default().event(update.name()).json_data(&update) {
+                Ok(event) => Some(Ok(event)),
+                Err(err) => {
+                    error!("Unable to serialize {} update: {err}", update.name());
+                    None
+                }
+            }
+        });
+    Ok(Sse::new(updates).keep_alive(KeepAlive::default()))
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            Error::AuthorizationMissing => (StatusCode::UNAUTHORIZED, "Unauthorized".to_owned()),
+            Error::InvalidSilence(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            Error::SilenceNotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
+            // Internal failures
+            err => {
+                error!("{err}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal Server Error".to_owned(),
+                )
+            }
+        };
+
+        let body = Json(serde_json::json!({
+            "error": error_message,
+        }));
+
+        (status, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn tokens() {
+        assert!(matches("secret", "secret"));
+        assert!(!matches("secreT", "secret"));
+        assert!(!matches("secret", "secrets"));
+        assert!(!matches("", "secret"));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::{scheduler::Context, ActionKind, ActionResult, ActionStatus, Alarm, AlarmKey, Service};
+use chrono::{DateTime, TimeDelta, Utc};
+use derive_get::Getters;
+use std::collections::HashMap;
+use tracing::{debug, error, info};
+
+pub const DEFAULT_AUTOSCALE_MIN_REPLICAS: u16 = 1;
+pub const MIN_AUTOSCALE_MIN_REPLICAS: u16 = 1;
+pub const DEFAULT_AUTOSCALE_STEP: u16 = 1;
+pub const DEFAULT_AUTOSCALE_SCALE_OUT_STABILIZATION_MINUTES: u32 = 0;
+pub const DEFAULT_AUTOSCALE_SCALE_IN_STABILIZATION_MINUTES: u32 = 10;
+pub const DEFAULT_AUTOSCALE_COOLDOWN_MINUTES: u32 = 5;
+pub const DEFAULT_AUTOSCALE_DRY_RUN: bool = false;
+
+/// Bounds and pace of the replicas changes
+#[derive(Getters, Copy, Clone, Debug)]
+pub struct Policy {
+    #[copy]
+    pub min_replicas: u16,
+    #[copy]
+    pub max_replicas: u16,
+    /// Replicas added when `CPU_UPPER_LIMIT_VCPUS` is in the ALARM state
+    #[copy]
+    pub scale_out_step: u16,
+    /// Replicas removed when `CPU_LOWER_LIMIT_VCPUS` is in the ALARM state
+    #[copy]
+    pub scale_in_step: u16,
+    /// Minutes the alarm must stay in the ALARM state before scaling out
+    #[copy]
+    pub scale_out_stabilization_minutes: u32,
+    /// Minutes the alarm must stay in the ALARM state before scaling in
+    #[copy]
+    pub scale_in_stabilization_minutes: u32,
+    /// Minutes after the replicas change in which they aren't changed again, so the load settles
+    #[copy]
+    pub cooldown_minutes: u32,
+    /// Records and notifies what would have been done, without changing the service
+    #[copy]
+    pub dry_run: bool,
+}
+
+impl Policy {
+    pub fn new(max_replicas: u16) -> Self {
+        Self {
+            min_replicas: DEFAULT_AUTOSCALE_MIN_REPLICAS,
+            max_replicas,
+            scale_out_step: DEFAULT_AUTOSCALE_STEP,
+            scale_in_step: DEFAULT_AUTOSCALE_STEP,
+            scale_out_stabilization_minutes: DEFAULT_AUTOSCALE_SCALE_OUT_STABILIZATION_MINUTES,
+            scale_in_stabilization_minutes: DEFAULT_AUTOSCALE_SCALE_IN_STABILIZATION_MINUTES,
+            cooldown_minutes: DEFAULT_AUTOSCALE_COOLDOWN_MINUTES,
+            dry_run: DEFAULT_AUTOSCALE_DRY_RUN,
+        }
+    }
+}
+
+/// Adds replicas to the service while its CPU is above the upper limit and removes them while it's below the lower one
+pub struct Autoscaler {
+    policy: Policy,
+    environment_id: String,
+    /// Since when the service is continuously above the upper limit
+    out_since: Option<DateTime<Utc>>,
+    /// Since when the service is continuously below the lower limit
+    in_since: Option<DateTime<Utc>>,
+    /// Failures included, so an unavailable API isn't called on every evaluation
+    scaled_at: Option<DateTime<Utc>>,
+    /// Alarm that found the service at its min or max replicas, so they aren't fetched again on every evaluation
+    bounded_at: Option<(Alarm, DateTime<Utc>)>,
+}
+
+impl Autoscaler {
+    pub fn new(policy: Policy, environment_id: String) -> Self {
+        Self {
+            policy,
+            environment_id,
+            out_since: None,
+            in_since: None,
+            scaled_at: None,
+            bounded_at: None,
+        }
+    }
+
+    /// Returns the decision taken on the state of the CPU alarms, if the replicas had to change
+    pub async fn evaluate(
+        &mut self,
+        alarm_states: &HashMap<AlarmKey, bool>,
+        context: &Context,
+        now: DateTime<Utc>,
+    ) -> Option<ActionResult> {
+        // Any replica above the limit needs help, but only if all are below the limit there's capacity to spare
+        let upper = alarm_states
+            .iter()
+            .filter(|(key, _)| key.alarm() == Alarm::CpuUpperLimitVcpus);
+        let lower = alarm_states
+            .iter()
+            .filter(|(key, _)| key.alarm() == Alarm::CpuLowerLimitVcpus);
+        let scale_out = upper.clone().any(|(_, on)| *on);
+        let scale_in = !scale_out && lower.clone().count() > 0 && lower.clone().all(|(_, on)| *on);
+
+        self.out_since = scale_out.then(|| self.out_since.unwrap_or(now));
+        self.in_since = scale_in.then(|| self.in_since.unwrap_or(now));
+
+        let (alarm, since, stabilization_minutes) = match (self.out_since, self.in_since) {
+            (Some(since), _) => (
+                Alarm::CpuUpperLimitVcpus,
+                since,
+                self.policy.scale_out_stabilization_minutes,
+            ),
+            (None, Some(since)) => (
+                Alarm::CpuLowerLimitVcpus,
+                since,
+                self.policy.scale_in_stabilization_minutes,
+            ),
+            (None, None) => return None,
+        };
+        let stabilization = TimeDelta::minutes(stabilization_minutes.into());
+        if since + stabilization > now {
+            debug!("Waiting for {alarm} to stabilize before scaling");
+            return None;
+        }
+
+        let cooldown = TimeDelta::minutes(self.policy.cooldown_minutes.into());
+        if self.scaled_at.is_some_and(|date| date + cooldown > now) {
+            debug!("Scaling is cooling down, {alarm} is ignored");
+            return None;
+        }
+        if self
+            .bounded_at
+            .is_some_and(|(bound, date)| bound == alarm && date + cooldown.max(stabilization) > now)
+        {
+            debug!("Service is still at its replicas bound, {alarm} is ignored");
+            return None;
+        }
+
+        let key = AlarmKey::from(alarm);
+        let result = |replicas, status, message| {
+            ActionResult::new(
+                key.clone(),
+                ActionKind::Replicas,
+                replicas,
+                status,
+                Some(message),
+                now,
+            )
+        };
+
+        let railway = &context.railway;
+        let current =
+            match Service::replicas(railway, &self.environment_id, &context.service_id).await {
+                Ok(current) => current,
+                Err(err) => {
+                    self.scaled_at = Some(now);
+                    error!("Unable to fetch the replicas to scale: {err}");
+                    return Some(result(None, ActionStatus::Failed, err.to_string()));
+                }
+            };
+
+        let (direction, target) = if alarm == Alarm::CpuUpperLimitVcpus {
+            if current >= self.policy.max_replicas {
+                debug!("Unable to scale out, service is at {current} replicas");
+                self.bounded_at = Some((alarm, now));
+                return None;
+            }
+            let target = current
+                .saturating_add(self.policy.scale_out_step)
+                .min(self.policy.max_replicas);
+            ("out", target)
+        } else {
+            if current <= self.policy.min_replicas {
+                debug!("Unable to scale in, service is at {current} replicas");
+                self.bounded_at = Some((alarm, now));
+                return None;
+            }
+            let target = current
+                .saturating_sub(self.policy.scale_in_step)
+                .max(self.policy.min_replicas);
+            ("in", target)
+        };
+
+        self.scaled_at = Some(now);
+        self.bounded_at = None;
+        let message = format!("Scaled {direction} from {current} to {target} replicas");
+        let (status, message) = if self.policy.dry_run {
+            (ActionStatus::DryRun, message)
+        } else {
+            match Service::set_replicas(railway, &self.environment_id, &context.service_id, target)
+                .await
+            {
+                Ok(()) => (ActionStatus::Executed, message),
+                Err(err) => (ActionStatus::Failed, format!("{message}: {err}")),
+            }
+        };
+
+        match status {
+            ActionStatus::Failed => error!("Autoscaling on {key} failed: {message}"),
+            _ => info!("Autoscaling on {key}: {message} ({status})"),
+        }
+        Some(result(Some(target), status, message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Autoscaler, Policy};
+    use crate::{
+        scheduler::Context, ActionStatus, Alarm, AlarmKey, FakeClock, FakeRailway, RailwayClient,
+    };
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use std::{collections::HashMap, sync::Arc};
+
+    #[tokio::test]
+    async fn evaluate() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let railway = Arc::new(FakeRailway::new());
+        for replicas in [2, 2, 3] {
+            railway.respond(
+                "instance",
+                serde_json::json!({ "serviceInstance": { "numReplicas": replicas } }),
+            );
+        }
+        railway.respond(
+            "replicas",
+            serde_json::json!({ "serviceInstanceUpdate": true }),
+        );
+        let context = Context {
+            clock: Arc::new(FakeClock::new(now)),
+            railway: RailwayClient::with_transport(railway.clone()),
+            project_id: "project".to_owned(),
+            service_id: "service".to_owned(),
+        };
+
+        let policy = Policy {
+            scale_out_step: 2,
+            scale_out_stabilization_minutes: 2,
+            cooldown_minutes: 5,
+            ..Policy::new(3)
+        };
+        let mut autoscaler = Autoscaler::new(policy, "environment".to_owned());
+        let upper = AlarmKey::from(Alarm::CpuUpperLimitVcpus);
+        let lower = AlarmKey::from(Alarm::CpuLowerLimitVcpus);
+        let states = |upper_on, lower_on| {
+            HashMap::from([(upper.clone(), upper_on), (lower.clone(), lower_on)])
+        };
+        let at = |minutes| now + TimeDelta::minutes(minutes);
+
+        // Waits for the stabilization window, then is clamped to the maximum
+        assert!(autoscaler
+            .evaluate(&states(true, false), &context, at(0))
+            .await
+            .is_none());
+        let result = autoscaler
+            .evaluate(&states(true, false), &context, at(2))
+            .await
+            .expect("scaled out");
+        assert_eq!(result.status(), ActionStatus::Executed);
+        assert_eq!(result.replicas(), Some(3));
+        assert_eq!(result.key(), &upper);
+        assert_eq!(railway.requests("replicas")[0]["replicas"], 3);
+
+        // Cooling down
+        assert!(autoscaler
+            .evaluate(&states(true, false), &context, at(4))
+            .await
+            .is_none());
+
+        // The alarm left the ALARM state, so stabilization starts over
+        assert!(autoscaler
+            .evaluate(&states(false, false), &context, at(8))
+            .await
+            .is_none());
+        assert!(autoscaler
+            .evaluate(&states(true, false), &context, at(9))
+            .await
+            .is_none());
+
+        // Scales in after its own stabilization window
+        assert!(autoscaler
+            .evaluate(&states(false, true), &context, at(10))
+            .await
+            .is_none());
+        let result = autoscaler
+            .evaluate(&states(false, true), &context, at(20))
+            .await
+            .expect("scaled in");
+        assert_eq!(result.replicas(), Some(1));
+        assert_eq!(railway.requests("replicas").len(), 2);
+
+        // At the maximum the replicas aren't fetched again until the cooldown elapses
+        assert!(autoscaler
+            .evaluate(&states(true, false), &context, at(26))
+            .await
+            .is_none());
+        assert!(autoscaler
+            .evaluate(&states(true, false), &context, at(28))
+            .await
+            .is_none());
+        assert_eq!(railway.requests("instance").len(), 3);
+        assert!(autoscaler
+            .evaluate(&states(true, false), &context, at(30))
+            .await
+            .is_none());
+        assert_eq!(railway.requests("instance").len(), 3);
+        assert!(autoscaler
+            .evaluate(&states(true, false), &context, at(33))
+            .await
+            .is_none());
+        assert_eq!(railway.requests("instance").len(), 4);
+    }
+}
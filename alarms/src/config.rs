@@ -1,40 +1,80 @@
-use crate::{Alarm, Error, Result};
-use derive_get::Getters;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::{
+    alarm::collect,
+    autoscale::{Autoscaler, Policy, MIN_AUTOSCALE_MIN_REPLICAS},
+    store::{DEFAULT_HISTORY_RETENTION_HOURS, DEFAULT_TRANSITIONS_RETENTION_DAYS},
+    Action, ActionKind, Alarm, AlarmConfig, AlarmKey, Error, Maintenance, Result, Schedule,
+    Statistic, Usage,
+};
+use common::{
+    action::MIN_ACTION_MAX_EXECUTIONS,
+    config::{
+        DEFAULT_DATA_POINTS, DEFAULT_DATA_POINTS_TO_ALARM, DEFAULT_PERIOD_SECS,
+        DEFAULT_PER_REPLICA, DEFAULT_STATISTIC, MIN_DATA_POINTS, MIN_DATA_POINTS_TO_ALARM,
+        MIN_FLAP_DATA_POINTS, MIN_PERIOD_SECS, MIN_WINDOW_MINUTES,
+    },
+    AlarmDefinition,
+};
+use std::{collections::HashMap, path::PathBuf};
 use strum::IntoEnumIterator;
-use tracing::{warn, debug};
+use tracing::{warn, debug, info};
 
-#[derive(Getters, Serialize, Deserialize, Clone, Debug)]
-pub struct AlarmConfig {
-    value: String,
-    #[copy]
-    period_minutes: u16,
-    #[copy]
-    data_points: u16,
-    #[copy]
-    data_points_to_alarm: u16,
-}
+const DEFAULT_PORT: u16 = 4001;
 
-const DEFAULT_PERIOD_MINUTES: u16 = 1;
-const MIN_PERIOD_MINUTES: u16 = 1;
+const DEFAULT_MAINTENANCE_DURATION_MINUTES: u32 = 60;
 
-const DEFAULT_DATA_POINTS: u16 = 5;
-const MIN_DATA_POINTS: u16 = 1;
+/// Suffixes of the environment variables used to configure each maintenance window
+const MAINTENANCE_SETTINGS: [&str; 3] = ["DURATION_MINUTES", "ALARMS", "SERVICES"];
 
-const DEFAULT_DATA_POINTS_TO_ALARM: u16 = 3;
-const MIN_DATA_POINTS_TO_ALARM: u16 = 1;
+const DEFAULT_BASELINE_FILE: &str = "baselines.json";
 
-pub fn required() -> Result<(String, String, String, String)> {
+const DEFAULT_HISTORY_DIRECTORY: &str = "history";
+
+const DEFAULT_DEFINITIONS_FILE: &str = "definitions.json";
+/// Attempts to load the alarms from the backend before falling back to the ones it last returned
+const BACKEND_ATTEMPTS: u32 = 5;
+/// Backoff of the first retry, doubled on each of the following ones
+const BACKEND_BACKOFF_SECS: u64 = 2;
+
+/// Suffixes of the environment variables used for granular configuration of each alarm
+const SETTINGS: [&str; 21] = [
+    "PERIOD_SECS",
+    "PERIOD_MINUTES",
+    "DATA_POINTS",
+    "DATA_POINTS_TO_ALARM",
+    "PER_REPLICA",
+    "MAX_LINES",
+    "STATISTIC",
+    "SUPPRESS",
+    "WINDOW_MINUTES",
+    "HORIZON_HOURS",
+    "RECOVERY",
+    "RECOVERY_DATA_POINTS",
+    "FLAP_LIMIT",
+    "FLAP_DATA_POINTS",
+    "ACTION",
+    "ACTION_REPLICAS",
+    "ACTION_COOLDOWN_MINUTES",
+    "ACTION_MAX_EXECUTIONS",
+    "ACTION_WINDOW_MINUTES",
+    "ACTION_DRY_RUN",
+    "VARIABLE",
+];
+
+/// Required to query the monitored service from Railway
+pub fn railway() -> Result<(String, String, String)> {
     let railway_api_token = std::env::var("RAILWAY_API_TOKEN")
         .map_err(|_| Error::MissingEnvVar("RAILWAY_API_TOKEN"))?;
-    let alarm_token =
-        std::env::var("ALARM_TOKEN").map_err(|_| Error::MissingEnvVar("ALARM_TOKEN"))?;
-
     let project_id = std::env::var("RAILWAY_PROJECT_ID")
         .map_err(|_| Error::MissingEnvVar("RAILWAY_PROJECT_ID"))?;
     let service_id = std::env::var("RAILWAY_MONITORED_SERVICE_ID")
         .map_err(|_| Error::MissingEnvVar("RAILWAY_MONITORED_SERVICE_ID"))?;
+    Ok((railway_api_token, project_id, service_id))
+}
+
+pub fn required() -> Result<(String, String, String, String)> {
+    let (railway_api_token, project_id, service_id) = railway()?;
+    let alarm_token =
+        std::env::var("ALARM_TOKEN").map_err(|_| Error::MissingEnvVar("ALARM_TOKEN"))?;
 
     if std::env::var("WEB_HOOK_URL").is_err() && std::env::var("PAGER_DUTY_TOKEN").is_err() {
         return Err(Error::MissingEnvVar(
@@ -54,13 +94,220 @@ pub fn required() -> Result<(String, String, String, String)> {
     Ok((railway_api_token, alarm_token, project_id, service_id))
 }
 
-pub fn optional() -> Result<HashMap<Alarm, AlarmConfig>> {
-    let default_period_minutes = std::env::var("PERIOD_MINUTES")
+/// An empty `STREAM_TOKEN` is left unset, so it never matches a missing token
+pub fn stream_token(alarm_token: &str) -> Result<Option<String>> {
+    match std::env::var("STREAM_TOKEN") {
+        Ok(token) if token == alarm_token => Err(common::Error::InvalidSetting(
+            "STREAM_TOKEN".to_owned(),
+            "must differ from ALARM_TOKEN".to_owned(),
+        )
+        .into()),
+        Ok(token) if !token.is_empty() => Ok(Some(token)),
+        _ => Ok(None),
+    }
+}
+
+pub fn port() -> Result<u16> {
+    std::env::var("PORT")
         .ok()
         .map(|value| value.parse::<u16>())
         .transpose()
-        .map_err(|err| Error::ParseIntWithMetadata(err, "PERIOD_MINUTES".into()))?
-        .unwrap_or(DEFAULT_PERIOD_MINUTES);
+        .map_err(|err| Error::ParseIntWithMetadata(err, "PORT".into()))
+        .map(|port| port.unwrap_or(DEFAULT_PORT))
+}
+
+/// Maintenance windows configured with `MAINTENANCE_<NAME>`
+pub fn maintenances() -> Result<Vec<Maintenance>> {
+    let mut maintenances = Vec::new();
+    for (env_name, value) in std::env::vars() {
+        let Some(name) = env_name.strip_prefix("MAINTENANCE_") else {
+            continue;
+        };
+
+        // Skips the granular configuration of the windows
+        if name.is_empty()
+            || MAINTENANCE_SETTINGS
+                .iter()
+                .any(|setting| name.ends_with(&format!("_{setting}")))
+        {
+            continue;
+        }
+
+        let duration_minutes_env_name = format!("{env_name}_DURATION_MINUTES");
+        let duration_minutes = std::env::var(&duration_minutes_env_name)
+            .ok()
+            .map(|value| value.parse::<u32>())
+            .transpose()
+            .map_err(|err| Error::ParseIntWithMetadata(err, duration_minutes_env_name))?
+            .unwrap_or(DEFAULT_MAINTENANCE_DURATION_MINUTES);
+        let schedule = Schedule::parse(&value, duration_minutes)?;
+
+        let list = |setting: &str| {
+            std::env::var(format!("{env_name}_{setting}"))
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(str::to_owned)
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        };
+        let alarms = list("ALARMS")
+            .into_iter()
+            .map(|alarm| alarm.to_lowercase())
+            .collect();
+
+        maintenances.push(Maintenance::new(
+            name.to_owned(),
+            schedule,
+            alarms,
+            list("SERVICES"),
+        ));
+    }
+
+    debug!("Maintenances: {maintenances:#?}");
+    Ok(maintenances)
+}
+
+/// Horizontal autoscaling of the service, enabled by `AUTOSCALE_MAX_REPLICAS`
+pub fn autoscale() -> Result<Option<Autoscaler>> {
+    let Ok(max_replicas) = std::env::var("AUTOSCALE_MAX_REPLICAS") else {
+        return Ok(None);
+    };
+    let max_replicas = max_replicas
+        .parse::<u16>()
+        .map_err(|err| Error::ParseIntWithMetadata(err, "AUTOSCALE_MAX_REPLICAS".into()))?;
+    let environment_id = std::env::var("RAILWAY_ENVIRONMENT_ID").map_err(|_| {
+        Error::MissingEnvVar("RAILWAY_ENVIRONMENT_ID is required to scale the service")
+    })?;
+
+    let replicas = |env_name: &str, default: u16| {
+        std::env::var(env_name)
+            .ok()
+            .map(|value| value.parse::<u16>())
+            .transpose()
+            .map_err(|err| Error::ParseIntWithMetadata(err, env_name.to_owned()))
+            .map(|value| value.unwrap_or(default))
+    };
+    let minutes = |env_name: &str, default: u32| {
+        std::env::var(env_name)
+            .ok()
+            .map(|value| value.parse::<u32>())
+            .transpose()
+            .map_err(|err| Error::ParseIntWithMetadata(err, env_name.to_owned()))
+            .map(|value| value.unwrap_or(default))
+    };
+
+    let defaults = Policy::new(max_replicas);
+    let policy = Policy {
+        min_replicas: replicas("AUTOSCALE_MIN_REPLICAS", defaults.min_replicas)?,
+        max_replicas,
+        scale_out_step: replicas("AUTOSCALE_SCALE_OUT_STEP", defaults.scale_out_step)?,
+        scale_in_step: replicas("AUTOSCALE_SCALE_IN_STEP", defaults.scale_in_step)?,
+        scale_out_stabilization_minutes: minutes(
+            "AUTOSCALE_SCALE_OUT_STABILIZATION_MINUTES",
+            defaults.scale_out_stabilization_minutes,
+        )?,
+        scale_in_stabilization_minutes: minutes(
+            "AUTOSCALE_SCALE_IN_STABILIZATION_MINUTES",
+            defaults.scale_in_stabilization_minutes,
+        )?,
+        cooldown_minutes: minutes("AUTOSCALE_COOLDOWN_MINUTES", defaults.cooldown_minutes)?,
+        dry_run: std::env::var("AUTOSCALE_DRY_RUN")
+            .ok()
+            .map(|value| value.parse::<bool>())
+            .transpose()
+            .map_err(|err| Error::ParseBoolWithMetadata(err, "AUTOSCALE_DRY_RUN".into()))?
+            .unwrap_or(defaults.dry_run),
+    };
+
+    if policy.min_replicas < MIN_AUTOSCALE_MIN_REPLICAS {
+        return Err(Error::InvalidAutoscale(format!(
+            "AUTOSCALE_MIN_REPLICAS can't be below {MIN_AUTOSCALE_MIN_REPLICAS}"
+        )));
+    }
+    if policy.min_replicas > policy.max_replicas {
+        return Err(Error::InvalidAutoscale(format!(
+            "AUTOSCALE_MIN_REPLICAS ({}) is above AUTOSCALE_MAX_REPLICAS ({max_replicas})",
+            policy.min_replicas
+        )));
+    }
+    if policy.scale_out_step == 0 || policy.scale_in_step == 0 {
+        return Err(Error::InvalidAutoscale(
+            "AUTOSCALE_SCALE_OUT_STEP and AUTOSCALE_SCALE_IN_STEP must be positive".to_owned(),
+        ));
+    }
+    if std::env::var(Alarm::CpuUpperLimitVcpus.to_string()).is_err()
+        && std::env::var(Alarm::CpuLowerLimitVcpus.to_string()).is_err()
+    {
+        warn!("Autoscaling is enabled, but neither CPU_UPPER_LIMIT_VCPUS nor CPU_LOWER_LIMIT_VCPUS are set");
+    }
+
+    debug!("Autoscale: {policy:#?}");
+    Ok(Some(Autoscaler::new(policy, environment_id)))
+}
+
+/// Railway volumes keep the history across deployments, if one is mounted
+pub fn history_path() -> PathBuf {
+    if let Ok(path) = std::env::var("HISTORY_PATH") {
+        return path.into();
+    }
+
+    match std::env::var("RAILWAY_VOLUME_MOUNT_PATH") {
+        Ok(path) => PathBuf::from(path).join(DEFAULT_HISTORY_DIRECTORY),
+        Err(_) => DEFAULT_HISTORY_DIRECTORY.into(),
+    }
+}
+
+pub fn history_retention_hours() -> Result<u32> {
+    std::env::var("HISTORY_RETENTION_HOURS")
+        .ok()
+        .map(|value| value.parse::<u32>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, "HISTORY_RETENTION_HOURS".into()))
+        .map(|hours| hours.unwrap_or(DEFAULT_HISTORY_RETENTION_HOURS))
+}
+
+pub fn transitions_retention_days() -> Result<u32> {
+    std::env::var("HISTORY_TRANSITIONS_RETENTION_DAYS")
+        .ok()
+        .map(|value| value.parse::<u32>())
+        .transpose()
+        .map_err(|err| {
+            Error::ParseIntWithMetadata(err, "HISTORY_TRANSITIONS_RETENTION_DAYS".into())
+        })
+        .map(|days| days.unwrap_or(DEFAULT_TRANSITIONS_RETENTION_DAYS))
+}
+
+/// Railway volumes keep the baselines across deployments, if one is mounted
+pub fn baseline_path() -> PathBuf {
+    if let Ok(path) = std::env::var("BASELINE_PATH") {
+        return path.into();
+    }
+
+    match std::env::var("RAILWAY_VOLUME_MOUNT_PATH") {
+        Ok(path) => PathBuf::from(path).join(DEFAULT_BASELINE_FILE),
+        Err(_) => DEFAULT_BASELINE_FILE.into(),
+    }
+}
+
+/// Alarms last loaded from the backend, kept in the Railway volume if one is mounted
+pub fn definitions_path() -> PathBuf {
+    if let Ok(path) = std::env::var("DEFINITIONS_PATH") {
+        return path.into();
+    }
+
+    match std::env::var("RAILWAY_VOLUME_MOUNT_PATH") {
+        Ok(path) => PathBuf::from(path).join(DEFAULT_DEFINITIONS_FILE),
+        Err(_) => DEFAULT_DEFINITIONS_FILE.into(),
+    }
+}
+
+pub fn optional() -> Result<HashMap<AlarmKey, AlarmConfig>> {
+    let default_period_secs =
+        period_secs("PERIOD_SECS", "PERIOD_MINUTES")?.unwrap_or(DEFAULT_PERIOD_SECS);
     let default_data_points = std::env::var("DATA_POINTS")
         .ok()
         .map(|value| value.parse::<u16>())
@@ -73,112 +320,462 @@ pub fn optional() -> Result<HashMap<Alarm, AlarmConfig>> {
         .transpose()
         .map_err(|err| Error::ParseIntWithMetadata(err, "DATA_POINTS_TO_ALARM".into()))?
         .unwrap_or(DEFAULT_DATA_POINTS_TO_ALARM);
+    let default_per_replica = std::env::var("PER_REPLICA")
+        .ok()
+        .map(|value| value.parse::<bool>())
+        .transpose()
+        .map_err(|err| Error::ParseBoolWithMetadata(err, "PER_REPLICA".into()))?
+        .unwrap_or(DEFAULT_PER_REPLICA);
+    let default_statistic = std::env::var("STATISTIC")
+        .ok()
+        .map(|value| value.parse::<Statistic>())
+        .transpose()?
+        .unwrap_or(DEFAULT_STATISTIC);
+
+    let defaults = AlarmConfig {
+        period_secs: default_period_secs,
+        data_points: default_data_points,
+        data_points_to_alarm: default_data_points_to_alarm,
+        per_replica: default_per_replica,
+        statistic: default_statistic,
+        ..AlarmConfig::default()
+    };
 
     let mut configs = HashMap::new();
     for alarm in Alarm::iter() {
-        if let Some(value) = std::env::var(alarm.to_string()).ok() {
-            // Short term solution to allow both alarm types with the same env var machinery
-            // The correct solution is having a AlarmWithPaylaod type that adds a value tuple to each variant of Alarm
-            if alarm != Alarm::HealthCheckFailed {
-                if let Err(err) = value.parse::<f64>() {
-                    return Err(Error::ParseFloatWithMetadata(err, alarm.to_string()));
+        if alarm.is_named() {
+            let prefix = format!("{alarm}_");
+            for (env_name, value) in std::env::vars() {
+                let Some(name) = env_name.strip_prefix(&prefix) else {
+                    continue;
+                };
+
+                // Skips the granular configuration of the named alarms
+                if name.is_empty()
+                    || SETTINGS
+                        .iter()
+                        .any(|setting| name == *setting || name.ends_with(&format!("_{setting}")))
+                {
+                    continue;
                 }
-            }
 
-            let period_minutes_env_name = format!("{alarm}_PERIOD_MINUTES");
-            let mut period_minutes = std::env::var(&period_minutes_env_name)
-                .ok()
-                .map(|value| value.parse::<u16>())
-                .transpose()
-                .map_err(|err| Error::ParseIntWithMetadata(err, period_minutes_env_name.clone()))?
-                .unwrap_or(default_period_minutes);
-            if period_minutes < MIN_PERIOD_MINUTES {
-                period_minutes = MIN_PERIOD_MINUTES;
-                warn!("{period_minutes_env_name} can't be below {MIN_PERIOD_MINUTES}, setting it to {MIN_PERIOD_MINUTES}");
+                let key = AlarmKey::named(alarm, name.to_owned());
+                if let Some(config) = alarm_config(&key, value, &defaults)? {
+                    configs.insert(key, config);
+                }
             }
-
-            let data_points_env_name = format!("{alarm}_DATA_POINTS");
-            let mut data_points = std::env::var(&data_points_env_name)
-                .ok()
-                .map(|value| value.parse::<u16>())
-                .transpose()
-                .map_err(|err| Error::ParseIntWithMetadata(err, data_points_env_name.clone()))?
-                .unwrap_or(default_data_points);
-            if data_points < MIN_DATA_POINTS {
-                data_points = MIN_DATA_POINTS;
-                warn!("{data_points_env_name} can't be below {MIN_DATA_POINTS}, setting it to {MIN_DATA_POINTS}");
+        } else if let Ok(value) = std::env::var(alarm.to_string()) {
+            let key = AlarmKey::from(alarm);
+            if let Some(config) = alarm_config(&key, value, &defaults)? {
+                configs.insert(key, config);
             }
+        }
+    }
 
-            let data_points_to_alarm_env_name = format!("{alarm}_DATA_POINTS_TO_ALARM");
-            let mut data_points_to_alarm = std::env::var(&data_points_to_alarm_env_name)
-                .ok()
-                .map(|value| value.parse::<u16>())
-                .transpose()
-                .map_err(|err| {
-                    Error::ParseIntWithMetadata(err, data_points_to_alarm_env_name.clone())
-                })?
-                .unwrap_or(default_data_points_to_alarm);
-            if data_points_to_alarm < MIN_DATA_POINTS_TO_ALARM {
-                data_points_to_alarm = MIN_DATA_POINTS_TO_ALARM;
-                warn!("{data_points_to_alarm_env_name} can't be below {MIN_DATA_POINTS_TO_ALARM}, setting it to {MIN_DATA_POINTS_TO_ALARM}");
-            }
+    validate_all(configs)
+}
 
-            configs.insert(
-                alarm,
-                AlarmConfig {
-                    value,
-                    period_minutes,
-                    data_points,
-                    data_points_to_alarm,
-                },
+/// Alarms managed through the backend, they replace the ones configured by environment variables if `BACKEND_URL` is set
+pub async fn definitions(
+    railway_api_token: &str,
+    project_id: &str,
+    service_id: &str,
+) -> Result<Option<HashMap<AlarmKey, AlarmConfig>>> {
+    let Ok(backend_url) = std::env::var("BACKEND_URL") else {
+        return Ok(None);
+    };
+
+    let url = format!(
+        "{}/v1/projects/{project_id}/services/{service_id}/alarms",
+        backend_url.trim_end_matches('/')
+    );
+    let path = definitions_path();
+    let definitions = match fetch_definitions(&url, railway_api_token).await {
+        Ok(definitions) => {
+            info!("Loaded {} alarms from the backend", definitions.len());
+            match serde_json::to_vec(&definitions) {
+                Ok(json) => {
+                    if let Err(err) = tokio::fs::write(&path, json).await {
+                        warn!("Unable to keep the alarms in {}: {err}", path.display());
+                    }
+                }
+                Err(err) => warn!("Unable to serialize the alarms: {err}"),
+            }
+            definitions
+        }
+        // Outages of the backend shouldn't stop the monitoring, rejected requests should
+        Err(err) if unavailable(&err) => {
+            let json = tokio::fs::read(&path).await.map_err(|_| err)?;
+            let definitions = serde_json::from_slice::<Vec<AlarmDefinition>>(&json)?;
+            warn!(
+                "Backend unavailable, using the {} alarms it last returned",
+                definitions.len()
             );
+            definitions
         }
+        Err(err) => return Err(err),
+    };
+
+    let mut configs = HashMap::new();
+    for definition in definitions {
+        // Validated when stored, but the rules may have changed since
+        if definition.config().check(definition.key())? {
+            configs.insert(definition.key().clone(), definition.config().clone());
+        }
+    }
+    validate_all(configs).map(Some)
+}
+
+/// Retries with backoff while the backend is unreachable or failing on its side
+async fn fetch_definitions(url: &str, railway_api_token: &str) -> Result<Vec<AlarmDefinition>> {
+    let mut attempt = 0;
+    loop {
+        match request_definitions(url, railway_api_token).await {
+            Err(err) if unavailable(&err) && attempt + 1 < BACKEND_ATTEMPTS => {
+                let delay = BACKEND_BACKOFF_SECS << attempt;
+                warn!("Unable to load the alarms from the backend, retrying in {delay}s: {err}");
+                tokio::time::sleep(std::time::Duration::from_secs(delay)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn request_definitions(url: &str, railway_api_token: &str) -> Result<Vec<AlarmDefinition>> {
+    let response = reqwest::Client::new()
+        .get(url)
+        .bearer_auth(railway_api_token)
+        .send()
+        .await
+        .map_err(|err| Error::BackendFailure(err, url.to_owned()))?;
+
+    let status = response.status();
+    if status != 200 {
+        return Err(Error::BackendStatusFailure(
+            status.as_u16(),
+            response
+                .text()
+                .await
+                .map_err(|err| Error::BackendFailure(err, url.to_owned()))?,
+        ));
     }
+    response
+        .json::<Vec<AlarmDefinition>>()
+        .await
+        .map_err(|err| Error::BackendFailure(err, url.to_owned()))
+}
+
+fn unavailable(err: &Error) -> bool {
+    match err {
+        Error::BackendFailure(..) => true,
+        Error::BackendStatusFailure(status, _) => *status >= 500,
+        _ => false,
+    }
+}
+
+/// Validates what can only be validated once every alarm is configured
+fn validate_all(configs: HashMap<AlarmKey, AlarmConfig>) -> Result<HashMap<AlarmKey, AlarmConfig>> {
+    AlarmConfig::validate_composites(&configs)?;
+
+    if configs
+        .iter()
+        .any(|(key, config)| collect::needs_limits(key, config))
+        && std::env::var("RAILWAY_ENVIRONMENT_ID").is_err()
+    {
+        return Err(Error::MissingEnvVar(
+            "RAILWAY_ENVIRONMENT_ID is required to fetch the limits of the service",
+        ));
+    }
+
     debug!("Configs: {configs:#?}");
     Ok(configs)
 }
 
+/// Reads the period in seconds, or in minutes for compatibility, rounded to a multiple of `MIN_PERIOD_SECS`
+fn period_secs(secs_env_name: &str, minutes_env_name: &str) -> Result<Option<u32>> {
+    let secs = match std::env::var(secs_env_name) {
+        Ok(value) => value
+            .parse::<u32>()
+            .map_err(|err| Error::ParseIntWithMetadata(err, secs_env_name.to_owned()))?,
+        Err(_) => match std::env::var(minutes_env_name) {
+            Ok(value) => value
+                .parse::<u32>()
+                .map_err(|err| Error::ParseIntWithMetadata(err, minutes_env_name.to_owned()))?
+                .saturating_mul(60),
+            Err(_) => return Ok(None),
+        },
+    };
+
+    let rounded = (secs / MIN_PERIOD_SECS).max(1) * MIN_PERIOD_SECS;
+    if rounded != secs {
+        warn!("{secs_env_name} must be a multiple of {MIN_PERIOD_SECS} and can't be below it, setting it to {rounded}");
+    }
+    Ok(Some(rounded))
+}
+
+/// Parses the granular configuration of the alarm, returns `None` if the alarm is disabled
+fn alarm_config(
+    key: &AlarmKey,
+    value: String,
+    defaults: &AlarmConfig,
+) -> Result<Option<AlarmConfig>> {
+    if !AlarmConfig::validate(key, &value)? {
+        return Ok(None);
+    }
+
+    let period_secs = period_secs(
+        &format!("{key}_PERIOD_SECS"),
+        &format!("{key}_PERIOD_MINUTES"),
+    )?
+    .unwrap_or(defaults.period_secs);
+
+    let data_points_env_name = format!("{key}_DATA_POINTS");
+    let mut data_points = std::env::var(&data_points_env_name)
+        .ok()
+        .map(|value| value.parse::<u16>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, data_points_env_name.clone()))?
+        .unwrap_or(defaults.data_points);
+    if data_points < MIN_DATA_POINTS {
+        data_points = MIN_DATA_POINTS;
+        warn!("{data_points_env_name} can't be below {MIN_DATA_POINTS}, setting it to {MIN_DATA_POINTS}");
+    }
+
+    let data_points_to_alarm_env_name = format!("{key}_DATA_POINTS_TO_ALARM");
+    let mut data_points_to_alarm = std::env::var(&data_points_to_alarm_env_name)
+        .ok()
+        .map(|value| value.parse::<u16>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, data_points_to_alarm_env_name.clone()))?
+        .unwrap_or(defaults.data_points_to_alarm);
+    if data_points_to_alarm < MIN_DATA_POINTS_TO_ALARM {
+        data_points_to_alarm = MIN_DATA_POINTS_TO_ALARM;
+        warn!("{data_points_to_alarm_env_name} can't be below {MIN_DATA_POINTS_TO_ALARM}, setting it to {MIN_DATA_POINTS_TO_ALARM}");
+    }
+
+    let per_replica_env_name = format!("{key}_PER_REPLICA");
+    let per_replica = std::env::var(&per_replica_env_name)
+        .ok()
+        .map(|value| value.parse::<bool>())
+        .transpose()
+        .map_err(|err| Error::ParseBoolWithMetadata(err, per_replica_env_name.clone()))?
+        .unwrap_or(defaults.per_replica);
+
+    let max_lines_env_name = format!("{key}_MAX_LINES");
+    let max_lines = std::env::var(&max_lines_env_name)
+        .ok()
+        .map(|value| value.parse::<u32>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, max_lines_env_name.clone()))?
+        .unwrap_or(defaults.max_lines);
+
+    let statistic = std::env::var(format!("{key}_STATISTIC"))
+        .ok()
+        .map(|value| value.parse::<Statistic>())
+        .transpose()?
+        .unwrap_or(defaults.statistic);
+
+    let suppress_env_name = format!("{key}_SUPPRESS");
+    let suppress = std::env::var(&suppress_env_name)
+        .ok()
+        .map(|value| value.parse::<bool>())
+        .transpose()
+        .map_err(|err| Error::ParseBoolWithMetadata(err, suppress_env_name.clone()))?
+        .unwrap_or(defaults.suppress);
+
+    let window_minutes_env_name = format!("{key}_WINDOW_MINUTES");
+    let mut window_minutes = std::env::var(&window_minutes_env_name)
+        .ok()
+        .map(|value| value.parse::<u32>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, window_minutes_env_name.clone()))?
+        .unwrap_or(defaults.window_minutes);
+    if window_minutes < MIN_WINDOW_MINUTES {
+        window_minutes = MIN_WINDOW_MINUTES;
+        warn!("{window_minutes_env_name} can't be below {MIN_WINDOW_MINUTES}, setting it to {MIN_WINDOW_MINUTES}");
+    }
+
+    let horizon_hours_env_name = format!("{key}_HORIZON_HOURS");
+    let horizon_hours = std::env::var(&horizon_hours_env_name)
+        .ok()
+        .map(|value| value.parse::<u32>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, horizon_hours_env_name.clone()))?
+        .unwrap_or(defaults.horizon_hours);
+
+    let recovery_env_name = format!("{key}_RECOVERY");
+    let recovery = std::env::var(&recovery_env_name)
+        .ok()
+        .map(|value| value.parse::<f64>())
+        .transpose()
+        .map_err(|err| Error::ParseFloatWithMetadata(err, recovery_env_name.clone()))?
+        .or(defaults.recovery);
+
+    let recovery_data_points_env_name = format!("{key}_RECOVERY_DATA_POINTS");
+    let mut recovery_data_points = std::env::var(&recovery_data_points_env_name)
+        .ok()
+        .map(|value| value.parse::<u16>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, recovery_data_points_env_name.clone()))?
+        .or(defaults.recovery_data_points);
+    if recovery_data_points.is_some_and(|points| points < MIN_DATA_POINTS) {
+        recovery_data_points = Some(MIN_DATA_POINTS);
+        warn!("{recovery_data_points_env_name} can't be below {MIN_DATA_POINTS}, setting it to {MIN_DATA_POINTS}");
+    }
+
+    let flap_limit_env_name = format!("{key}_FLAP_LIMIT");
+    let flap_limit = std::env::var(&flap_limit_env_name)
+        .ok()
+        .map(|value| value.parse::<u16>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, flap_limit_env_name.clone()))?
+        .unwrap_or(defaults.flap_limit);
+
+    let flap_data_points_env_name = format!("{key}_FLAP_DATA_POINTS");
+    let mut flap_data_points = std::env::var(&flap_data_points_env_name)
+        .ok()
+        .map(|value| value.parse::<u16>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, flap_data_points_env_name.clone()))?
+        .unwrap_or(defaults.flap_data_points);
+    if flap_data_points < MIN_FLAP_DATA_POINTS {
+        flap_data_points = MIN_FLAP_DATA_POINTS;
+        warn!("{flap_data_points_env_name} can't be below {MIN_FLAP_DATA_POINTS}, setting it to {MIN_FLAP_DATA_POINTS}");
+    }
+
+    let config = AlarmConfig {
+        value,
+        period_secs,
+        data_points,
+        data_points_to_alarm,
+        per_replica,
+        max_lines,
+        statistic,
+        suppress,
+        window_minutes,
+        horizon_hours,
+        recovery,
+        recovery_data_points,
+        flap_limit,
+        flap_data_points,
+        action: action(key)?,
+        variable: std::env::var(format!("{key}_VARIABLE")).ok(),
+    };
+    if key.alarm() == Alarm::Anomaly
+        && !Usage::VARIABLES.contains(&config.anomaly_variable(key).as_str())
+    {
+        return Err(common::Error::UnknownVariable(
+            format!("{key}_VARIABLE"),
+            Usage::VARIABLES.join(", "),
+        )
+        .into());
+    }
+
+    Ok(Some(config))
+}
+
+/// Parses the action run when the alarm enters the ALARM state, if any
+fn action(key: &AlarmKey) -> Result<Option<Action>> {
+    let Ok(kind) = std::env::var(format!("{key}_ACTION")) else {
+        return Ok(None);
+    };
+    let mut action = Action::new(kind.parse::<ActionKind>()?);
+
+    let replicas_env_name = format!("{key}_ACTION_REPLICAS");
+    action.replicas = std::env::var(&replicas_env_name)
+        .ok()
+        .map(|value| value.parse::<u16>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, replicas_env_name.clone()))?;
+    if action.kind == ActionKind::Replicas && action.replicas.is_none() {
+        return Err(Error::MissingSetting(replicas_env_name));
+    }
+
+    let cooldown_minutes_env_name = format!("{key}_ACTION_COOLDOWN_MINUTES");
+    action.cooldown_minutes = std::env::var(&cooldown_minutes_env_name)
+        .ok()
+        .map(|value| value.parse::<u32>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, cooldown_minutes_env_name.clone()))?
+        .unwrap_or(action.cooldown_minutes);
+
+    let max_executions_env_name = format!("{key}_ACTION_MAX_EXECUTIONS");
+    action.max_executions = std::env::var(&max_executions_env_name)
+        .ok()
+        .map(|value| value.parse::<u16>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, max_executions_env_name.clone()))?
+        .unwrap_or(action.max_executions);
+    if action.max_executions < MIN_ACTION_MAX_EXECUTIONS {
+        action.max_executions = MIN_ACTION_MAX_EXECUTIONS;
+        warn!("{max_executions_env_name} can't be below {MIN_ACTION_MAX_EXECUTIONS}, setting it to {MIN_ACTION_MAX_EXECUTIONS}");
+    }
+
+    let window_minutes_env_name = format!("{key}_ACTION_WINDOW_MINUTES");
+    action.window_minutes = std::env::var(&window_minutes_env_name)
+        .ok()
+        .map(|value| value.parse::<u32>())
+        .transpose()
+        .map_err(|err| Error::ParseIntWithMetadata(err, window_minutes_env_name.clone()))?
+        .unwrap_or(action.window_minutes);
+
+    let dry_run_env_name = format!("{key}_ACTION_DRY_RUN");
+    action.dry_run = std::env::var(&dry_run_env_name)
+        .ok()
+        .map(|value| value.parse::<bool>())
+        .transpose()
+        .map_err(|err| Error::ParseBoolWithMetadata(err, dry_run_env_name.clone()))?
+        .unwrap_or(action.dry_run);
+
+    Ok(Some(action))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::Alarm;
     use strum::IntoEnumIterator;
 
     #[test]
-    fn all() {
-        // All
-        for alarm in Alarm::iter() {
+    fn optional() {
+        // All thresholds, the other alarms need names or booleans
+        let thresholds = Alarm::iter().filter(|alarm| {
+            !alarm.is_named()
+                && !matches!(
+                    alarm,
+                    Alarm::DeploymentCrashed | Alarm::DeploymentFailed | Alarm::DeploymentRemoved
+                )
+        });
+        std::env::set_var("RAILWAY_ENVIRONMENT_ID", "environment");
+        for alarm in thresholds.clone() {
             std::env::set_var(alarm.to_string(), "3");
         }
 
-        let config = super::get().expect("unable to get config from env vars");
-        assert_eq!(config.len(), Alarm::iter().count());
-        for (_alarm, config) in config {
-            assert_eq!(config.value(), 3.);
+        let config = super::optional().expect("unable to get config from env vars");
+        assert_eq!(config.len(), thresholds.clone().count());
+        for (_key, config) in config {
+            assert_eq!(config.value(), "3");
         }
 
-        for alarm in Alarm::iter() {
+        for alarm in thresholds {
             std::env::remove_var(alarm.to_string());
         }
+        std::env::remove_var("RAILWAY_ENVIRONMENT_ID");
 
         // Parse Error
         std::env::set_var("CPU_LOWER_LIMIT_VCPUS", "a");
-        assert!(super::get().is_err());
-
-        // Zero
-        std::env::set_var("CPU_LOWER_LIMIT_VCPUS", "0");
-        let config = super::get().expect("unable to get config from env vars");
-        assert!(config.is_empty());
+        assert!(super::optional().is_err());
 
         // Default
         std::env::set_var("CPU_LOWER_LIMIT_VCPUS", "5.");
-        let config = super::get().expect("unable to get config from env vars");
+        let config = super::optional().expect("unable to get config from env vars");
         assert_eq!(config.len(), 1);
 
         let cpu_lower = config
-            .get(&Alarm::CpuLowerLimitVcpus)
+            .get(&Alarm::CpuLowerLimitVcpus.into())
             .expect("no lower limit for cpu found");
-        assert_eq!(cpu_lower.value(), 5.);
-        assert_eq!(cpu_lower.period_minutes(), 1);
+        assert_eq!(cpu_lower.value(), "5.");
+        assert_eq!(cpu_lower.period_secs(), 60);
         assert_eq!(cpu_lower.data_points(), 5);
         assert_eq!(cpu_lower.data_points_to_alarm(), 3);
 
@@ -186,14 +783,14 @@ mod tests {
         std::env::set_var("CPU_LOWER_LIMIT_VCPUS", "1");
         std::env::set_var("CPU_LOWER_LIMIT_VCPUS_PERIOD_MINUTES", "0");
 
-        let config = super::get().expect("unable to get config from env vars");
+        let config = super::optional().expect("unable to get config from env vars");
         assert_eq!(config.len(), 1);
 
         let cpu_lower = config
-            .get(&Alarm::CpuLowerLimitVcpus)
+            .get(&Alarm::CpuLowerLimitVcpus.into())
             .expect("no lower limit for cpu found");
-        assert_eq!(cpu_lower.value(), 1.);
-        assert_eq!(cpu_lower.period_minutes(), 1);
+        assert_eq!(cpu_lower.value(), "1");
+        assert_eq!(cpu_lower.period_secs(), super::MIN_PERIOD_SECS);
 
         // Custom
         // Setting env vars affects the whole process, so we avoid doing that from many tests
@@ -207,22 +804,22 @@ mod tests {
         std::env::set_var("CPU_LOWER_LIMIT_VCPUS_DATA_POINTS_TO_ALARM", "1");
 
         std::env::set_var("CPU_UPPER_LIMIT_VCPUS", "4");
-        let config = super::get().expect("unable to get config from env vars");
+        let config = super::optional().expect("unable to get config from env vars");
         assert_eq!(config.len(), 2);
 
         let cpu_lower = config
-            .get(&Alarm::CpuLowerLimitVcpus)
+            .get(&Alarm::CpuLowerLimitVcpus.into())
             .expect("no lower limit for cpu found");
-        assert_eq!(cpu_lower.value(), 1.);
-        assert_eq!(cpu_lower.period_minutes(), 5);
+        assert_eq!(cpu_lower.value(), "1");
+        assert_eq!(cpu_lower.period_secs(), 300);
         assert_eq!(cpu_lower.data_points(), 6);
         assert_eq!(cpu_lower.data_points_to_alarm(), 1);
 
         let cpu_upper = config
-            .get(&Alarm::CpuUpperLimitVcpus)
+            .get(&Alarm::CpuUpperLimitVcpus.into())
             .expect("no upper limit for cpu found");
-        assert_eq!(cpu_upper.value(), 4.);
-        assert_eq!(cpu_upper.period_minutes(), 3);
+        assert_eq!(cpu_upper.value(), "4");
+        assert_eq!(cpu_upper.period_secs(), 180);
         assert_eq!(cpu_upper.data_points(), 2);
         assert_eq!(cpu_upper.data_points_to_alarm(), 2);
     }
@@ -1,42 +1,60 @@
 use chrono::{DateTime, Utc};
-use std::num::{ParseFloatError, ParseIntError};
-use tracing::*;
+use std::{
+    num::{ParseFloatError, ParseIntError},
+    str::ParseBoolError,
+};
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[remain::sorted]
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+    #[error("authorization missing")]
+    AuthorizationMissing,
+    #[error("backend reqwest failure for {1}: {0}")]
+    BackendFailure(reqwest::Error, String),
+    #[error("backend request failed with status {0}: {1}")]
+    BackendStatusFailure(u16, String),
+    #[error(transparent)]
+    Common(#[from] common::Error),
     #[error("date out of range: {0} - {1}")]
     DateOutOfRange(DateTime<Utc>, i64),
     #[error("date truncation")]
     DateTruncation,
+    #[error("service {0} has no deployment")]
+    DeploymentMissing(String),
     #[error(transparent)]
     DotEnv(#[from] dotenv::Error),
     #[error(transparent)]
     HMacInvalidLength(#[from] hmac::digest::InvalidLength),
+    #[error("invalid autoscale: {0}")]
+    InvalidAutoscale(String),
+    #[error("invalid recording {0}: {1}")]
+    InvalidRecording(String, String),
+    #[error("invalid schedule {0}: {1}")]
+    InvalidSchedule(String, String),
+    #[error("invalid silence: {0}")]
+    InvalidSilence(String),
     #[error("invalid time delta: secs = {0}, nano = {1}")]
     InvalidTimeDelta(i64, i64),
+    #[error("io error for {1}: {0}")]
+    IoWithMetadata(std::io::Error, String),
     #[error(transparent)]
     Json(#[from] serde_json::Error),
     #[error("json error: {0} with payload {1:#?}")]
     JsonWithMetadata(serde_json::Error, serde_json::Value),
     #[error("missing env var: {0}")]
     MissingEnvVar(&'static str),
+    #[error("missing setting: {0}")]
+    MissingSetting(String),
+    #[error("parse bool error for {1}: {0}")]
+    ParseBoolWithMetadata(ParseBoolError, String),
     #[error("parse int error for {1}: {0}")]
     ParseFloatWithMetadata(ParseFloatError, String),
     #[error("parse int error for {1}: {0}")]
     ParseIntWithMetadata(ParseIntError, String),
-    #[error("railway responded with: {0:?}")]
-    Railway(Vec<String>),
-    #[error("railway reqwest body error for {1}: {0} ({2:#?})")]
-    RailwayBody(reqwest::Error, &'static str, serde_json::Value),
-    #[error("railway data missing: {0}")]
-    RailwayDataMissing(&'static str),
-    #[error("railway reqwest failure for {1}: {0} ({2:#?})")]
-    RailwayFailure(reqwest::Error, &'static str, serde_json::Value),
-    #[error("railway request failed with status {0}: {1}")]
-    RailwayStatusFailure(u16, String),
+    #[error("silence {0} not found")]
+    SilenceNotFound(u64),
     #[error("railway reqwest body error for {1}: {0}")]
     WebHookBody(reqwest::Error, String),
     #[error("webhook reqwest failure for {1}: {0}")]
@@ -1,21 +1,48 @@
+mod action;
 mod alarm;
+mod api;
+mod autoscale;
+mod clock;
 mod config;
 mod error;
-mod railway;
+mod replay;
+mod scheduler;
+mod silence;
+mod store;
+mod stream;
+#[cfg(any(test, feature = "testing"))]
+mod testing;
 
-pub use alarm::{emit, Alarm, AlarmPayload, AlarmState};
-pub use config::AlarmConfig;
-pub use error::{Error, Result};
-pub use railway::{
-    service::{Service, Usage},
-    Railway, RailwayError, RailwayResponse,
+pub use action::{ActionResult, ActionStatus, Actions};
+pub use alarm::{emit, AlarmPayload, Band, Baselines, History, Trend};
+pub use autoscale::{Autoscaler, Policy};
+pub use clock::{Clock, SystemClock};
+#[cfg(any(test, feature = "testing"))]
+pub use common::FakeRailway;
+pub use common::{
+    Action, ActionKind, Alarm, AlarmConfig, AlarmKey, AlarmState, Deployment, DeploymentStatus,
+    Expression, Function, HttpTransport, Limits, Log, Operator, RailwayClient, RailwayError,
+    RailwayResponse, Service, Statistic, Transport, Usage,
 };
+pub use error::{Error, Result};
+pub use replay::{replay, Probe, Recording, Sample};
+pub use scheduler::{Evaluation, Source};
+pub use silence::{Cron, Maintenance, Schedule, Silence, SilenceRequest, Silences};
+pub use store::{Range, StateValue, Store, Transition};
+pub use stream::{Filter, Update, Updates};
+#[cfg(any(test, feature = "testing"))]
+pub use testing::FakeClock;
 
-use chrono::{DateTime, SubsecRound, TimeDelta, Timelike, Utc};
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+};
+use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
 
-const MIN_PERIOD_SECS: u16 = 60;
+/// Evaluations waiting to be emitted before the tasks wait for the emitter
+const CHANNEL_CAPACITY: usize = 64;
 
 pub async fn run() -> Result<()> {
     let shutdown = CancellationToken::new();
@@ -27,79 +54,267 @@ pub async fn run() -> Result<()> {
         shutdown_clone.cancel();
     });
 
+    let result = match HttpTransport::new() {
+        Ok(transport) => run_with(Arc::new(SystemClock), Arc::new(transport), shutdown).await,
+        Err(err) => Err(err.into()),
+    };
+    shutdown_task.abort();
+    result
+}
+
+/// Alarms notified by the emitter, for the actions task to act on
+struct Triggered {
+    alarms: HashMap<AlarmKey, AlarmState>,
+    alarm_states: HashMap<AlarmKey, bool>,
+    active_states: HashMap<AlarmKey, bool>,
+}
+
+/// Runs the actions and the autoscaler for each evaluation emitted, notifying their results, until the emitter stops
+async fn run_actions(
+    mut actions: Actions,
+    mut autoscaler: Option<Autoscaler>,
+    mut receiver: mpsc::UnboundedReceiver<Triggered>,
+    context: Arc<scheduler::Context>,
+    updates: Updates,
+    alarm_token: String,
+) {
+    while let Some(Triggered {
+        alarms,
+        alarm_states,
+        active_states,
+    }) = receiver.recv().await
+    {
+        let now = context.clock.now();
+        let mut results = actions.run(&alarms, &context, now).await;
+        if let Some(autoscaler) = &mut autoscaler {
+            if let Some(result) = autoscaler.evaluate(&alarm_states, &context, now).await {
+                actions.record(std::slice::from_ref(&result));
+                results.push(result);
+            }
+        }
+        for result in &results {
+            updates.send(Update::Action(result.clone()));
+        }
+        let service_id = &context.service_id;
+        alarm::emit(
+            HashMap::new(),
+            &active_states,
+            &results,
+            &alarm_token,
+            service_id,
+        )
+        .await;
+    }
+}
+
+/// Monitors the service until shutdown, with the time and the Railway API provided
+pub async fn run_with(
+    clock: Arc<dyn Clock>,
+    transport: Arc<dyn Transport>,
+    shutdown: CancellationToken,
+) -> Result<()> {
     let (railway_api_token, alarm_token, project_id, service_id) = config::required()?;
 
-    let mut alarm_payloads: HashMap<_, _> = config::optional()?
+    let configs = match config::definitions(&railway_api_token, &project_id, &service_id).await? {
+        Some(configs) => configs,
+        None => config::optional()?,
+    };
+    let alarm_payloads: HashMap<_, _> = configs
         .into_iter()
-        .map(|(alarm, config)| (alarm, AlarmPayload::from_config(config)))
+        .map(|(key, config)| (key, AlarmPayload::from_config(config)))
         .collect();
+    let actions = Actions::new(
+        alarm_payloads
+            .iter()
+            .filter_map(|(key, payload)| Some((key.clone(), payload.config().action()?)))
+            .collect(),
+    );
 
-    // Set start date to the previous minute
-    let mut start_date = initial_tick(MIN_PERIOD_SECS)?;
-
-    while let Some(alarms) = alarm::collect::alarms(
-        start_date,
-        &mut alarm_payloads,
-        &shutdown,
-        MIN_PERIOD_SECS,
-        &railway_api_token,
-        &project_id,
-        &service_id,
-    )
-    .await
-    {
-        alarm::emit(alarms, &alarm_payloads, &alarm_token, &service_id).await;
-
-        // Should never fail, but if it does let the process monitor restart us, this should fix the problem
-        start_date = next_tick(start_date, MIN_PERIOD_SECS)?;
-
-        // Casting like this is dangerous, but since we ensure that the min value is 0 we can trust that the i64 will fit u64 without wrapping
-        let secs_to_sleep = secs_to_sleep(start_date, MIN_PERIOD_SECS);
-        if secs_to_sleep > 0 {
-            let sleep = tokio::time::sleep(Duration::from_secs(secs_to_sleep));
-            tokio::pin!(sleep);
-            tokio::select! {
-                _ = shutdown.cancelled() => break,
-                _ = &mut sleep => {},
+    let autoscaler = config::autoscale()?;
+    let store = Arc::new(
+        Store::load(
+            config::history_path(),
+            config::history_retention_hours()?,
+            config::transitions_retention_days()?,
+        )
+        .await?,
+    );
+
+    let updates = Updates::new(project_id.clone(), service_id.clone());
+
+    let variables = alarm_payloads.keys().map(AlarmKey::variable).collect();
+    let silences = Arc::new(Mutex::new(Silences::new(
+        config::maintenances()?,
+        variables,
+    )));
+
+    let state = api::ApiState {
+        silences: Arc::clone(&silences),
+        actions: actions.results(),
+        store: Arc::clone(&store),
+        updates: updates.clone(),
+        token: alarm_token.as_str().into(),
+        stream_token: config::stream_token(&alarm_token)?.map(Into::into),
+        clock: Arc::clone(&clock),
+    };
+    let router = api::router(state);
+    let port = config::port()?;
+    let server_shutdown = shutdown.clone();
+    let server_task = tokio::spawn(async move {
+        if let Err(err) = api::serve(router, port, server_shutdown).await {
+            error!("API server failed: {err}");
+        }
+    });
+
+    // Each source is evaluated by its own task, composites are left to the emitter
+    let mut sources: HashMap<Source, HashMap<AlarmKey, AlarmPayload>> = HashMap::new();
+    let mut composite_payloads = HashMap::new();
+    for (key, payload) in alarm_payloads {
+        match Source::of(key.alarm()) {
+            Some(source) => {
+                sources.entry(source).or_default().insert(key, payload);
+            }
+            None => {
+                composite_payloads.insert(key, payload);
             }
         }
     }
 
-    shutdown_task.abort();
+    // Known before the first evaluation, so composites can always refer to every alarm
+    let mut source_states: HashMap<Source, HashMap<AlarmKey, bool>> = sources
+        .iter()
+        .map(|(source, payloads)| {
+            let states = payloads.keys().map(|key| (key.clone(), false)).collect();
+            (*source, states)
+        })
+        .collect();
 
-    Ok(())
-}
+    let context = Arc::new(scheduler::Context {
+        clock: Arc::clone(&clock),
+        railway: RailwayClient::with_transport(transport).with_token(railway_api_token),
+        project_id,
+        service_id: service_id.clone(),
+    });
+    // Kept while emitting, so the emitter only stops on shutdown even if no task is running
+    let (sender, mut receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    let tasks = sources
+        .into_iter()
+        .map(|(source, payloads)| {
+            scheduler::spawn(
+                source,
+                payloads,
+                Arc::clone(&context),
+                sender.clone(),
+                shutdown.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
 
-fn initial_tick(period_secs: u16) -> Result<DateTime<Utc>> {
-    Utc::now()
-        .round_subsecs(0)
-        .with_second(0)
-        .ok_or(Error::DateTruncation)?
-        .checked_add_signed(
-            TimeDelta::new(-i64::from(period_secs), 0)
-                .ok_or(Error::InvalidTimeDelta(-i64::from(period_secs), 0))?,
-        )
-        .ok_or(Error::DateOutOfRange(Utc::now(), -i64::from(period_secs)))
-}
+    // Actions wait on Railway's mutations, so they run on their own task and their results are notified once done,
+    // without delaying the notifications of the alarms
+    let (action_sender, action_receiver) = mpsc::unbounded_channel();
+    let actions_task = tokio::spawn(run_actions(
+        actions,
+        autoscaler,
+        action_receiver,
+        Arc::clone(&context),
+        updates.clone(),
+        alarm_token.clone(),
+    ));
+
+    loop {
+        let evaluation = tokio::select! {
+            evaluation = receiver.recv() => evaluation,
+            _ = shutdown.cancelled() => None,
+        };
+        let Some(Evaluation {
+            source,
+            mut alarms,
+            states,
+            usages,
+        }) = evaluation
+        else {
+            break;
+        };
+        source_states.insert(source, states);
 
-fn next_tick(date: DateTime<Utc>, period_secs: u16) -> Result<DateTime<Utc>> {
-    let date = date
-        .checked_add_signed(
-            TimeDelta::new(period_secs.into(), 0)
-                .ok_or(Error::InvalidTimeDelta(period_secs.into(), 0))?,
+        let mut alarm_states = source_states
+            .values()
+            .flatten()
+            .map(|(key, on)| (key.clone(), *on))
+            .collect::<HashMap<_, _>>();
+        let suppressed =
+            alarm::collect::composites(&mut alarms, &mut composite_payloads, &alarm_states);
+        for (key, payload) in &composite_payloads {
+            alarm_states.insert(key.clone(), payload.state());
+        }
+
+        let alarms_on = alarm_states
+            .iter()
+            .filter(|(_, on)| **on)
+            .map(|(key, _)| match key.replica() {
+                Some(replica) => format!("{key} ({replica})"),
+                None => key.to_string(),
+            })
+            .collect::<Vec<_>>();
+        if !alarms_on.is_empty() {
+            info!("Alarms on: {}", alarms_on.join(", "));
+        }
+
+        // Silenced transitions are kept too, the history reflects the service regardless of notifications
+        let transitions = alarms
+            .values()
+            .map(|state| Transition::new(state, service_id.clone(), clock.now()))
+            .collect::<Vec<_>>();
+        for transition in &transitions {
+            updates.send(Update::Transition(transition.clone()));
+        }
+        for usage in &usages {
+            updates.send(Update::Usage(usage.clone()));
+        }
+        if let Err(err) = store.record(transitions, usages).await {
+            error!("Unable to persist the history: {err}");
+        }
+
+        // Evaluation continues while silenced, only the notifications are withheld
+        let (alarms, notified_states) = {
+            let mut silences = silences.lock().unwrap_or_else(PoisonError::into_inner);
+            let alarms = silences.withhold(alarms, &alarm_states, &service_id, clock.now());
+            let notified_states = silences.notified(&alarm_states, &service_id, clock.now());
+            (alarms, notified_states)
+        };
+        // Active alarms are sent along with the webhook, unless silenced or suppressed by a composite
+        let active_states = notified_states
+            .into_iter()
+            .filter(|(key, _)| !suppressed.contains(&key.variable()))
+            .collect::<HashMap<_, _>>();
+        alarm::emit(
+            alarms.clone(),
+            &active_states,
+            &[],
+            &alarm_token,
+            &service_id,
         )
-        .ok_or(Error::DateOutOfRange(date, period_secs.into()))?;
-    Ok(date)
-}
+        .await;
+        let _ = action_sender.send(Triggered {
+            alarms,
+            alarm_states,
+            active_states,
+        });
+    }
+    drop(sender);
+    drop(action_sender);
+    let _ = actions_task.await;
 
-// tokio::time::interval is simpler, but it doesn't give us the date range to send to railway graphql API
-fn secs_to_sleep(date: DateTime<Utc>, period_secs: u16) -> u64 {
-    let secs_since_last: u64 = Utc::now().signed_duration_since(date).num_seconds().max(0) as u64;
+    shutdown.cancel();
+    let _ = server_task.await;
 
-    if secs_since_last < period_secs.into() {
-        // Figures out how much to sleep until next period
-        u64::from(period_secs) - secs_since_last
-    } else {
-        0
+    // A task that failed stopped the others, report it so the process monitor restarts us
+    let mut result = Ok(());
+    for task in tasks {
+        if let Ok(Err(err)) = task.await {
+            result = Err(err);
+        }
     }
+    result
 }
@@ -31,7 +31,14 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    alarms::run().await?;
+    // Replays the alarms over recorded usage instead of monitoring the service
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.split_first() {
+        Some((command, args)) if command == "replay" => {
+            alarms::replay(alarms::Recording::parse(args)?).await?
+        }
+        _ => alarms::run().await?,
+    }
 
     Ok(())
 }
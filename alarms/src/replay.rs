@@ -0,0 +1,481 @@
+use crate::{
+    alarm::collect, config, scheduler, Alarm, AlarmKey, AlarmPayload, AlarmState, Baselines, Error,
+    History, RailwayClient, Result, Service, Source, Usage,
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+use tracing::{info, warn};
+
+/// Where the samples to replay come from
+#[derive(Debug, Clone)]
+pub enum Recording {
+    /// JSON lines, or CSV with a header if the extension is `.csv`
+    File(PathBuf),
+    /// Usage of the monitored service fetched from Railway
+    Railway {
+        start_date: DateTime<Utc>,
+        end_date: DateTime<Utc>,
+    },
+}
+
+impl Recording {
+    /// Either a path, or the start and end dates in RFC 3339
+    pub fn parse(args: &[String]) -> Result<Self> {
+        match args {
+            [path] => Ok(Self::File(PathBuf::from(path))),
+            [start_date, end_date] => {
+                let parse = |date: &str| {
+                    DateTime::parse_from_rfc3339(date)
+                        .map(|date| date.with_timezone(&Utc))
+                        .map_err(|err| Error::InvalidRecording(date.to_owned(), err.to_string()))
+                };
+                let (start_date, end_date) = (parse(start_date)?, parse(end_date)?);
+                if start_date >= end_date {
+                    return Err(Error::InvalidRecording(
+                        format!("{start_date} {end_date}"),
+                        "the start date must be before the end date".to_owned(),
+                    ));
+                }
+                Ok(Self::Railway {
+                    start_date,
+                    end_date,
+                })
+            }
+            _ => Err(Error::InvalidRecording(
+                args.join(" "),
+                "expected a file or the start and end dates".to_owned(),
+            )),
+        }
+    }
+}
+
+/// Healthcheck result recorded, replicas are probed individually if the alarm is per replica
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Probe {
+    #[copy]
+    date: DateTime<Utc>,
+    #[serde(default)]
+    replica: Option<String>,
+    #[copy]
+    healthy: bool,
+}
+
+/// Each line of a recording is either the usage of a window or a healthcheck result
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Sample {
+    Usage(Usage),
+    Probe(Probe),
+}
+
+enum Event {
+    /// End of a window of usage of the period
+    Window(u32),
+    Probe(Vec<(Option<String>, bool)>),
+}
+
+/// Runs the configured alarms over the recording with a virtual clock, logging every transition that would
+/// have been emitted. Deployment alarms aren't replayed and silences don't apply
+pub async fn replay(recording: Recording) -> Result<()> {
+    let (composite_payloads, alarm_payloads): (HashMap<_, _>, HashMap<_, _>) = config::optional()?
+        .into_iter()
+        .map(|(key, config)| (key, AlarmPayload::from_config(config)))
+        .partition(|(key, _)| key.alarm() == Alarm::Composite);
+
+    let samples = match recording {
+        Recording::File(path) => read(&path).await?,
+        Recording::Railway {
+            start_date,
+            end_date,
+        } => fetch(&alarm_payloads, start_date, end_date).await?,
+    };
+
+    let transitions = evaluate(alarm_payloads, composite_payloads, samples);
+    for (date, state) in &transitions {
+        log_transition(*date, state);
+    }
+    info!("Replay finished with {} transitions", transitions.len());
+    Ok(())
+}
+
+/// Transitions of the alarms over the samples, in order. Usage alarms are evaluated at the end of the windows of
+/// their periods over the samples measured during them, like while running
+fn evaluate(
+    mut alarm_payloads: HashMap<AlarmKey, AlarmPayload>,
+    mut composite_payloads: HashMap<AlarmKey, AlarmPayload>,
+    samples: Vec<Sample>,
+) -> Vec<(DateTime<Utc>, AlarmState)> {
+    let usages = usages(&samples);
+
+    let periods = collect::periods(
+        alarm_payloads
+            .iter()
+            .filter(|(key, _)| Source::of(key.alarm()) == Some(Source::Usage))
+            .map(|(_, payload)| payload),
+    );
+    let shortest = periods.first().copied();
+    let mut events: BTreeMap<_, Vec<Event>> = BTreeMap::new();
+    if let (Some((first, _)), Some((last, _))) = (usages.first(), usages.last()) {
+        // Only the windows recorded from their start are evaluated
+        let start_date = first.start_date();
+        let windows = collect::windows(&periods, last.end_date(), |period_secs, _| {
+            let offset = start_date.timestamp().rem_euclid(period_secs.into());
+            match offset {
+                0 => start_date,
+                offset => start_date + TimeDelta::seconds(i64::from(period_secs) - offset),
+            }
+        });
+        for (window_end, period_secs) in windows {
+            events
+                .entry(window_end)
+                .or_default()
+                .push(Event::Window(period_secs));
+        }
+    }
+    let mut probes: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for sample in samples {
+        if let Sample::Probe(probe) = sample {
+            probes
+                .entry(probe.date())
+                .or_default()
+                .push((probe.replica, probe.healthy));
+        }
+    }
+    for (date, replicas) in probes {
+        events.entry(date).or_default().push(Event::Probe(replicas));
+    }
+
+    let retention_minutes = alarm_payloads
+        .iter()
+        .filter(|(key, _)| key.alarm().is_trend())
+        .map(|(_, payload)| payload.config().window_minutes())
+        .max()
+        .unwrap_or(0);
+    let mut history = History::new(retention_minutes);
+    // Learned from the recording, the persisted baselines are left untouched
+    let mut baselines = Baselines::default();
+
+    let mut transitions = Vec::new();
+    for (date, events) in events {
+        let mut alarms = HashMap::new();
+        for event in events {
+            match event {
+                Event::Window(period_secs) => {
+                    let window_start = date - TimeDelta::seconds(period_secs.into());
+                    let mut samples = collect::within(&usages, window_start, date);
+                    let measured = samples.iter().map(|(usage, _)| usage.secs()).sum::<f64>();
+                    if measured < f64::from(period_secs) {
+                        warn!("Usage missing from {window_start} to {date}, skipping the window");
+                        continue;
+                    }
+
+                    collect::fill_replicas(&mut samples);
+                    collect::evaluate_window(
+                        &mut alarms,
+                        &mut alarm_payloads,
+                        &mut history,
+                        &mut baselines,
+                        (shortest == Some(period_secs)).then_some(&mut Vec::new()),
+                        samples,
+                        period_secs,
+                    );
+                }
+                Event::Probe(replicas) => {
+                    collect::process_healthcheck(&mut alarms, &mut alarm_payloads, replicas)
+                }
+            }
+        }
+
+        let alarm_states = alarm_payloads
+            .iter()
+            .map(|(key, payload)| (key.clone(), payload.state()))
+            .collect();
+        collect::composites(&mut alarms, &mut composite_payloads, &alarm_states);
+
+        let mut alarms = alarms.into_values().collect::<Vec<_>>();
+        alarms.sort_by_key(|state| state.key().to_string());
+        transitions.extend(alarms.into_iter().map(|state| (date, state)));
+    }
+    transitions
+}
+
+fn log_transition(date: DateTime<Utc>, state: &AlarmState) {
+    let key = state.key();
+    let target = match key.replica() {
+        Some(replica) => format!("{key} ({replica})"),
+        None => key.to_string(),
+    };
+    let state = if state.on() { "ALARM" } else { "OK" };
+    info!("{} {target} {state}", date.to_rfc3339());
+}
+
+async fn read(path: &PathBuf) -> Result<Vec<Sample>> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|err| Error::IoWithMetadata(err, path.display().to_string()))?;
+
+    let is_csv = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let header = if is_csv {
+        lines
+            .next()
+            .map(|(_, line)| line.split(',').map(str::trim).collect::<Vec<_>>())
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    let mut samples = Vec::new();
+    for (index, line) in lines {
+        let value = if is_csv {
+            // Empty cells are left out, so optional fields can be omitted
+            let row = header
+                .iter()
+                .zip(line.split(',').map(str::trim))
+                .filter(|(_, cell)| !cell.is_empty())
+                .map(|(name, cell)| {
+                    let value = match (cell.parse::<f64>(), cell.parse::<bool>()) {
+                        (Ok(number), _) => serde_json::json!(number),
+                        (_, Ok(boolean)) => serde_json::json!(boolean),
+                        _ => serde_json::json!(cell),
+                    };
+                    ((*name).to_owned(), value)
+                })
+                .collect::<serde_json::Map<_, _>>();
+            serde_json::Value::Object(row)
+        } else {
+            serde_json::from_str(line).map_err(|err| {
+                Error::InvalidRecording(
+                    format!("{}:{}", path.display(), index + 1),
+                    err.to_string(),
+                )
+            })?
+        };
+
+        let sample = serde_json::from_value(value.clone())
+            .map_err(|err| Error::JsonWithMetadata(err, value))?;
+        samples.push(sample);
+    }
+    Ok(samples)
+}
+
+/// Fetches the usage of every sample of the range, like the alarms would have while running
+async fn fetch(
+    alarm_payloads: &HashMap<AlarmKey, AlarmPayload>,
+    start_date: DateTime<Utc>,
+    end_date: DateTime<Utc>,
+) -> Result<Vec<Sample>> {
+    let (railway_api_token, project_id, service_id) = config::railway()?;
+    let railway = RailwayClient::new()?.with_token(railway_api_token);
+
+    let limits = if alarm_payloads
+        .iter()
+        .any(|(key, payload)| collect::needs_limits(key, payload.config()))
+    {
+        let environment_id = std::env::var("RAILWAY_ENVIRONMENT_ID")
+            .map_err(|_| Error::MissingEnvVar("RAILWAY_ENVIRONMENT_ID"))?;
+        warn!("Replaying with the current limits of the service, they may have changed since");
+        Some(Service::limits(&railway, &environment_id, &service_id).await?)
+    } else {
+        None
+    };
+
+    // Only usage is fetched, the periods of the other alarms don't change how often
+    let usage_payloads = alarm_payloads
+        .iter()
+        .filter(|(key, _)| Source::of(key.alarm()) == Some(Source::Usage))
+        .map(|(key, payload)| (key.clone(), payload.clone()))
+        .collect();
+    let sample_secs = collect::sample_secs(scheduler::tick_secs(&usage_payloads));
+    info!("Fetching the usage from {start_date} to {end_date} every {sample_secs} seconds");
+
+    let mut samples = Vec::new();
+    for (mut usage, mut replicas) in Service::usage_samples(
+        &railway,
+        &project_id,
+        &service_id,
+        start_date,
+        end_date,
+        sample_secs,
+    )
+    .await?
+    {
+        if let Some(limits) = limits {
+            usage.set_limits(limits, replicas.len());
+            for replica in &mut replicas {
+                replica.set_limits(limits, 1);
+            }
+        }
+
+        samples.push(Sample::Usage(usage));
+        samples.extend(replicas.into_iter().map(Sample::Usage));
+    }
+    Ok(samples)
+}
+
+/// Usage of the whole service along with the usage of each replica, oldest first
+fn usages(samples: &[Sample]) -> Vec<(Usage, Vec<Usage>)> {
+    let mut windows: BTreeMap<_, (Option<Usage>, Vec<Usage>)> = BTreeMap::new();
+    for sample in samples {
+        if let Sample::Usage(usage) = sample {
+            let window = windows
+                .entry((usage.start_date(), usage.end_date()))
+                .or_default();
+            match usage.replica() {
+                Some(_) => window.1.push(usage.clone()),
+                None => window.0 = Some(usage.clone()),
+            }
+        }
+    }
+
+    let mut usages = Vec::new();
+    for ((start_date, end_date), (usage, replicas)) in windows {
+        match usage {
+            Some(usage) => usages.push((usage, replicas)),
+            None => warn!("Usage of the whole service missing from {start_date} to {end_date}"),
+        }
+    }
+    usages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, Sample};
+    use crate::{
+        alarm::collect,
+        scheduler::{Context, SourceState},
+        Alarm, AlarmConfig, AlarmKey, AlarmPayload, AlarmState, Baselines, FakeClock, FakeRailway,
+        History, RailwayClient, Statistic,
+    };
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use std::{collections::HashMap, sync::Arc};
+    use tokio_util::sync::CancellationToken;
+
+    #[tokio::test]
+    async fn live() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        // CPU of each replica per minute, the second one only runs for a few minutes
+        let a = [1., 1., 3., 1., 1., 0.2, 0.2, 1., 3., 3.];
+        let b = [(3, 2.5), (4, 2.5), (5, 0.1)];
+        let values = |cpus: &[(i64, f64)]| {
+            cpus.iter()
+                .map(|(minute, cpu)| {
+                    let date = start + TimeDelta::minutes(*minute);
+                    serde_json::json!({ "ts": date.timestamp(), "value": cpu })
+                })
+                .collect::<Vec<_>>()
+        };
+        let a_values = a
+            .iter()
+            .enumerate()
+            .map(|(minute, cpu)| (minute as i64, *cpu))
+            .collect::<Vec<_>>();
+        let railway = Arc::new(FakeRailway::new());
+        railway.respond(
+            "metrics",
+            serde_json::json!({
+                "metrics": [
+                    {
+                        "measurement": "CPU_USAGE",
+                        "tags": { "deploymentInstanceId": "a" },
+                        "values": values(&a_values),
+                    },
+                    {
+                        "measurement": "CPU_USAGE",
+                        "tags": { "deploymentInstanceId": "b" },
+                        "values": values(&b),
+                    },
+                ],
+            }),
+        );
+        let context = Context {
+            clock: Arc::new(FakeClock::new(start)),
+            railway: RailwayClient::with_transport(railway.clone()),
+            project_id: "project".to_owned(),
+            service_id: "service".to_owned(),
+        };
+
+        let payloads = || {
+            HashMap::from([
+                (
+                    AlarmKey::from(Alarm::CpuUpperLimitVcpus),
+                    AlarmPayload::from_config(AlarmConfig {
+                        value: "2".to_owned(),
+                        period_secs: 120,
+                        data_points: 1,
+                        data_points_to_alarm: 1,
+                        statistic: Statistic::Maximum,
+                        per_replica: true,
+                        ..AlarmConfig::default()
+                    }),
+                ),
+                (
+                    AlarmKey::from(Alarm::CpuLowerLimitVcpus),
+                    AlarmPayload::from_config(AlarmConfig {
+                        value: "0.5".to_owned(),
+                        period_secs: 60,
+                        data_points: 1,
+                        data_points_to_alarm: 1,
+                        ..AlarmConfig::default()
+                    }),
+                ),
+            ])
+        };
+
+        // Evaluated every minute while running, the usage retained is the recording replayed
+        let mut alarm_payloads = payloads();
+        let mut state = SourceState {
+            history: History::new(0),
+            baselines: Baselines::default(),
+            unmeasured_since: HashMap::new(),
+            replica_ids: HashMap::new(),
+        };
+        let mut usages = Vec::new();
+        let mut transitions = Vec::new();
+        for minute in 0..a.len() as i64 {
+            let alarms = collect::usage_alarms(
+                start + TimeDelta::minutes(minute),
+                &mut alarm_payloads,
+                &mut state,
+                &mut usages,
+                &CancellationToken::new(),
+                60,
+                &context,
+            )
+            .await
+            .expect("shut down");
+            let date = start + TimeDelta::minutes(minute + 1);
+            transitions.extend(alarms.into_values().map(|state| (date, state)));
+        }
+
+        let transition = |(date, state): &(_, AlarmState)| {
+            (*date, state.key().clone(), state.on(), state.measurement())
+        };
+        let mut live = transitions.iter().map(transition).collect::<Vec<_>>();
+        live.sort_by_key(|(date, key, ..)| (*date, key.to_string(), key.replica().clone()));
+        let mut replayed = evaluate(
+            payloads(),
+            HashMap::new(),
+            usages.into_iter().map(Sample::Usage).collect(),
+        )
+        .iter()
+        .map(transition)
+        .collect::<Vec<_>>();
+        replayed.sort_by_key(|(date, key, ..)| (*date, key.to_string(), key.replica().clone()));
+
+        assert_eq!(live.len(), 7);
+        assert_eq!(replayed, live);
+    }
+}
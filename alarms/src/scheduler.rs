@@ -0,0 +1,262 @@
+use crate::{
+    alarm::collect, Alarm, AlarmKey, AlarmPayload, AlarmState, Baselines, Clock, Error, History,
+    RailwayClient, Result, Usage,
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use strum::Display;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+/// Used when no alarm of the source is configured
+const DEFAULT_TICK_SECS: u32 = 60;
+
+/// Baselines are persisted this often and at shutdown, a crash loses at most this much learning
+const BASELINE_SAVE_MINUTES: i64 = 10;
+
+/// Where an alarm gets its measurements from, each source is evaluated by its own task
+#[derive(Display, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Source {
+    Deployment,
+    HealthCheck,
+    Usage,
+}
+
+impl Source {
+    /// Composites have no source, they are evaluated by the emitter from the state of the others
+    pub fn of(alarm: Alarm) -> Option<Self> {
+        match alarm {
+            Alarm::Composite => None,
+            Alarm::HealthCheckFailed => Some(Self::HealthCheck),
+            alarm if collect::is_deployment_alarm(alarm) => Some(Self::Deployment),
+            _ => Some(Self::Usage),
+        }
+    }
+}
+
+/// Sent by a task to the emitter after every evaluation of its alarms
+#[derive(Debug)]
+pub struct Evaluation {
+    pub source: Source,
+    /// Alarms that changed state
+    pub alarms: HashMap<AlarmKey, AlarmState>,
+    /// State of every alarm of the source, replicas come and go between evaluations
+    pub states: HashMap<AlarmKey, bool>,
+    /// Usage measured since the last evaluation, kept by the history store
+    pub usages: Vec<Usage>,
+}
+
+pub struct Context {
+    pub clock: Arc<dyn Clock>,
+    pub railway: RailwayClient,
+    pub project_id: String,
+    pub service_id: String,
+}
+
+/// Kept by the task of a source between its evaluations
+pub struct SourceState {
+    /// Usage of the whole service retained for the trend alarms
+    pub history: History,
+    /// Bands learned by the anomaly alarms
+    pub baselines: Baselines,
+    /// Start of the windows of each period of usage that couldn't be measured yet, they are backfilled
+    pub unmeasured_since: HashMap<u32, DateTime<Utc>>,
+    /// Deployment instance reported by each address probed by the health checks
+    pub replica_ids: HashMap<String, String>,
+}
+
+/// Runs the alarms of the source on the greatest period that divides all of theirs, until shutdown. Usage is only
+/// fetched for the periods that elapsed on each tick, so a short period doesn't make the others query more often
+pub fn spawn(
+    source: Source,
+    alarm_payloads: HashMap<AlarmKey, AlarmPayload>,
+    context: Arc<Context>,
+    sender: mpsc::Sender<Evaluation>,
+    shutdown: CancellationToken,
+) -> JoinHandle<Result<()>> {
+    tokio::spawn(async move {
+        let result = schedule(source, alarm_payloads, &context, &sender, &shutdown).await;
+        if let Err(err) = &result {
+            // Stops the other tasks, so the process monitor restarts us
+            error!("{source} alarms stopped: {err}");
+            shutdown.cancel();
+        }
+        result
+    })
+}
+
+async fn schedule(
+    source: Source,
+    mut alarm_payloads: HashMap<AlarmKey, AlarmPayload>,
+    context: &Context,
+    sender: &mpsc::Sender<Evaluation>,
+    shutdown: &CancellationToken,
+) -> Result<()> {
+    let tick_secs = tick_secs(&alarm_payloads);
+
+    // Trend alarms need the usage of the service retained for their whole window
+    let retention_minutes = alarm_payloads
+        .iter()
+        .filter(|(key, _)| key.alarm().is_trend())
+        .map(|(_, payload)| payload.config().window_minutes())
+        .max()
+        .unwrap_or(0);
+    let anomalies = alarm_payloads
+        .keys()
+        .any(|key| key.alarm() == Alarm::Anomaly);
+    let baselines = if anomalies {
+        Baselines::load(crate::config::baseline_path()).await
+    } else {
+        Baselines::default()
+    };
+
+    info!("Evaluating {source} alarms every {tick_secs} seconds");
+
+    // Set start date to the previous tick
+    let mut start_date = initial_tick(context.clock.now(), tick_secs)?;
+    let mut state = SourceState {
+        history: History::new(retention_minutes),
+        baselines,
+        unmeasured_since: HashMap::new(),
+        replica_ids: HashMap::new(),
+    };
+    let mut baselines_saved = context.clock.now();
+
+    loop {
+        let mut usages = Vec::new();
+        let alarms = match source {
+            Source::Deployment => {
+                collect::deployment_alarms(
+                    start_date,
+                    &mut alarm_payloads,
+                    shutdown,
+                    tick_secs,
+                    context,
+                )
+                .await
+            }
+            Source::HealthCheck => {
+                collect::healthcheck_alarms(&mut alarm_payloads, &mut state.replica_ids, shutdown)
+                    .await
+            }
+            Source::Usage => {
+                collect::usage_alarms(
+                    start_date,
+                    &mut alarm_payloads,
+                    &mut state,
+                    &mut usages,
+                    shutdown,
+                    tick_secs,
+                    context,
+                )
+                .await
+            }
+        };
+        let Some(alarms) = alarms else {
+            break;
+        };
+
+        if anomalies
+            && context.clock.now() - baselines_saved >= TimeDelta::minutes(BASELINE_SAVE_MINUTES)
+        {
+            save_baselines(&state.baselines).await;
+            baselines_saved = context.clock.now();
+        }
+
+        let states = alarm_payloads
+            .iter()
+            .map(|(key, payload)| (key.clone(), payload.state()))
+            .collect();
+        let evaluation = Evaluation {
+            source,
+            alarms,
+            states,
+            usages,
+        };
+        // The emitter only stops on shutdown
+        if sender.send(evaluation).await.is_err() {
+            break;
+        }
+
+        // Should never fail, but if it does let the process monitor restart us, this should fix the problem
+        start_date = next_tick(start_date, tick_secs)?;
+
+        // Usage missed while asleep is backfilled by the next tick, instead of catching up one tick at a time
+        if source == Source::Usage {
+            start_date = start_date.max(initial_tick(context.clock.now(), tick_secs)?);
+        }
+
+        let secs_to_sleep = secs_to_sleep(context.clock.now(), start_date, tick_secs);
+        if secs_to_sleep > 0 {
+            let sleep = context.clock.sleep(Duration::from_secs(secs_to_sleep));
+            tokio::pin!(sleep);
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = &mut sleep => {},
+            }
+        }
+    }
+
+    if anomalies {
+        save_baselines(&state.baselines).await;
+    }
+    Ok(())
+}
+
+async fn save_baselines(baselines: &Baselines) {
+    if let Err(err) = baselines.save().await {
+        error!("Unable to persist baselines: {err}");
+    }
+}
+
+/// Every period is a multiple of the tick, so each alarm is evaluated at the end of its own period
+pub fn tick_secs(alarm_payloads: &HashMap<AlarmKey, AlarmPayload>) -> u32 {
+    alarm_payloads
+        .values()
+        .map(|payload| payload.config().period_secs())
+        .reduce(gcd)
+        .unwrap_or(DEFAULT_TICK_SECS)
+}
+
+fn initial_tick(now: DateTime<Utc>, period_secs: u32) -> Result<DateTime<Utc>> {
+    let timestamp = now.timestamp();
+    let period_secs = i64::from(period_secs);
+    DateTime::from_timestamp(timestamp - timestamp.rem_euclid(period_secs), 0)
+        .ok_or(Error::DateTruncation)?
+        .checked_add_signed(
+            TimeDelta::new(-period_secs, 0).ok_or(Error::InvalidTimeDelta(-period_secs, 0))?,
+        )
+        .ok_or(Error::DateOutOfRange(now, -period_secs))
+}
+
+fn next_tick(date: DateTime<Utc>, period_secs: u32) -> Result<DateTime<Utc>> {
+    let date = date
+        .checked_add_signed(
+            TimeDelta::new(period_secs.into(), 0)
+                .ok_or(Error::InvalidTimeDelta(period_secs.into(), 0))?,
+        )
+        .ok_or(Error::DateOutOfRange(date, period_secs.into()))?;
+    Ok(date)
+}
+
+// tokio::time::interval is simpler, but it doesn't give us the date range to send to railway graphql API
+fn secs_to_sleep(now: DateTime<Utc>, date: DateTime<Utc>, period_secs: u32) -> u64 {
+    // Casting like this is dangerous, but since we ensure that the min value is 0 we can trust that the i64 will fit u64 without wrapping
+    let secs_since_last: u64 = now.signed_duration_since(date).num_seconds().max(0) as u64;
+
+    if secs_since_last < period_secs.into() {
+        // Figures out how much to sleep until next period
+        u64::from(period_secs) - secs_since_last
+    } else {
+        0
+    }
+}
+
+pub fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
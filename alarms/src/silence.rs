@@ -0,0 +1,424 @@
+use crate::{AlarmKey, AlarmState, Error, Result};
+use chrono::{DateTime, Datelike, TimeDelta, Timelike, Utc};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::info;
+
+/// Recurring schedule with the five fields of cron: minute, hour, day of month, month and day of week
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cron {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days_of_month: Vec<bool>,
+    months: Vec<bool>,
+    days_of_week: Vec<bool>,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl Cron {
+    /// Supports `*`, numbers, ranges (`1-5`), lists (`1,3`) and steps (`*/15`), Sunday is both `0` and `7`
+    pub fn parse(source: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidSchedule(source.to_owned(), reason.to_owned());
+
+        let fields = source.split_whitespace().collect::<Vec<_>>();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            return Err(invalid("expected 5 fields"));
+        };
+
+        let mut days_of_week =
+            field(days_of_week, 0, 7).ok_or_else(|| invalid("invalid day of week"))?;
+        days_of_week[0] |= days_of_week[7];
+        days_of_week.truncate(7);
+
+        Ok(Self {
+            minutes: field(minutes, 0, 59).ok_or_else(|| invalid("invalid minute"))?,
+            hours: field(hours, 0, 23).ok_or_else(|| invalid("invalid hour"))?,
+            days_of_month: field(days_of_month, 1, 31)
+                .ok_or_else(|| invalid("invalid day of month"))?,
+            months: field(months, 1, 12).ok_or_else(|| invalid("invalid month"))?,
+            days_of_week,
+            any_day_of_month: days_of_month == "*",
+            any_day_of_week: fields[4] == "*",
+        })
+    }
+
+    pub fn matches(&self, date: DateTime<Utc>) -> bool {
+        let day_of_month = self.days_of_month[date.day0() as usize];
+        let day_of_week = self.days_of_week[date.weekday().num_days_from_sunday() as usize];
+
+        // Like cron, if both days are restricted matching either of them is enough
+        let day = match (self.any_day_of_month, self.any_day_of_week) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        };
+
+        day && self.minutes[date.minute() as usize]
+            && self.hours[date.hour() as usize]
+            && self.months[date.month0() as usize]
+    }
+}
+
+/// Values allowed by the field, indexed from `min`, `None` if it's invalid
+fn field(source: &str, min: u32, max: u32) -> Option<Vec<bool>> {
+    let mut allowed = vec![false; (max - min + 1) as usize];
+    for item in source.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0)?),
+            None => (item, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+                None => {
+                    let value = range.parse().ok()?;
+                    // A step without a range starts at the value and goes until the end, like cron
+                    if item.contains('/') {
+                        (value, max)
+                    } else {
+                        (value, value)
+                    }
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            allowed[(value - min) as usize] = true;
+        }
+    }
+    Some(allowed)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schedule {
+    /// Starts whenever the cron matches and lasts for the duration
+    Cron(Cron, u32),
+    Once(DateTime<Utc>, DateTime<Utc>),
+}
+
+impl Schedule {
+    /// Either `<start>/<end>` in RFC 3339 or a cron expression
+    pub fn parse(source: &str, duration_minutes: u32) -> Result<Self> {
+        match source.split_once('/') {
+            Some((start, end)) if source.split_whitespace().count() == 1 => {
+                let parse = |date: &str| {
+                    DateTime::parse_from_rfc3339(date.trim())
+                        .map(|date| date.with_timezone(&Utc))
+                        .map_err(|err| Error::InvalidSchedule(source.to_owned(), err.to_string()))
+                };
+                Ok(Self::Once(parse(start)?, parse(end)?))
+            }
+            _ => Ok(Self::Cron(Cron::parse(source)?, duration_minutes)),
+        }
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        match self {
+            Self::Once(start, end) => *start <= now && now < *end,
+            // Looks for a start in the last minutes covered by the duration
+            Self::Cron(cron, duration_minutes) => (0..i64::from(*duration_minutes))
+                .any(|minutes| cron.matches(now - TimeDelta::minutes(minutes))),
+        }
+    }
+}
+
+/// Planned window where the notifications of the alarms are withheld
+#[derive(Getters, Clone, Debug)]
+pub struct Maintenance {
+    name: String,
+    schedule: Schedule,
+    /// Variables of the alarms muted, all of them if empty
+    alarms: Vec<String>,
+    /// Services muted, all of them if empty
+    services: Vec<String>,
+}
+
+impl Maintenance {
+    pub fn new(
+        name: String,
+        schedule: Schedule,
+        alarms: Vec<String>,
+        services: Vec<String>,
+    ) -> Self {
+        Self {
+            name,
+            schedule,
+            alarms,
+            services,
+        }
+    }
+}
+
+/// Ad-hoc silence created through the API
+#[derive(Getters, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Silence {
+    #[copy]
+    id: u64,
+    /// Variables of the alarms muted, all of them if empty
+    alarms: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[copy]
+    created_at: DateTime<Utc>,
+    #[copy]
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SilenceRequest {
+    #[serde(default)]
+    pub alarms: Vec<String>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub duration_minutes: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+pub struct Silences {
+    maintenances: Vec<Maintenance>,
+    silences: Vec<Silence>,
+    /// Variables of the configured alarms, silences can only refer to them
+    variables: Vec<String>,
+    /// State last notified of the alarms whose transitions were withheld
+    withheld: HashMap<AlarmKey, bool>,
+    next_id: u64,
+}
+
+impl Silences {
+    pub fn new(maintenances: Vec<Maintenance>, variables: Vec<String>) -> Self {
+        Self {
+            maintenances,
+            variables,
+            next_id: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Silences that didn't expire yet
+    pub fn silences(&self, now: DateTime<Utc>) -> Vec<Silence> {
+        self.silences
+            .iter()
+            .filter(|silence| silence.expires_at > now)
+            .cloned()
+            .collect()
+    }
+
+    pub fn add(&mut self, request: SilenceRequest, now: DateTime<Utc>) -> Result<Silence> {
+        let expires_at = match (request.expires_at, request.duration_minutes) {
+            (Some(expires_at), None) => expires_at,
+            (None, Some(duration_minutes)) => now + TimeDelta::minutes(duration_minutes.into()),
+            _ => {
+                return Err(Error::InvalidSilence(
+                    "either expiresAt or durationMinutes must be set".to_owned(),
+                ))
+            }
+        };
+        if expires_at <= now {
+            return Err(Error::InvalidSilence("silence already expired".to_owned()));
+        }
+
+        let alarms = request
+            .alarms
+            .iter()
+            .map(|alarm| alarm.to_lowercase())
+            .collect::<Vec<_>>();
+        if let Some(alarm) = alarms.iter().find(|alarm| !self.variables.contains(alarm)) {
+            return Err(Error::InvalidSilence(format!("unknown alarm {alarm}")));
+        }
+
+        let silence = Silence {
+            id: self.next_id,
+            alarms,
+            reason: request.reason,
+            created_at: now,
+            expires_at,
+        };
+        self.next_id += 1;
+        self.silences.push(silence.clone());
+        info!("Silence {} created until {expires_at}", silence.id);
+        Ok(silence)
+    }
+
+    /// Returns `false` if the silence doesn't exist
+    pub fn remove(&mut self, id: u64) -> bool {
+        let len = self.silences.len();
+        self.silences.retain(|silence| silence.id != id);
+        len != self.silences.len()
+    }
+
+    pub fn is_silenced(&self, key: &AlarmKey, service_id: &str, now: DateTime<Utc>) -> bool {
+        let variable = key.variable();
+        let muted = |alarms: &[String]| alarms.is_empty() || alarms.contains(&variable);
+
+        self.maintenances.iter().any(|maintenance| {
+            (maintenance.services.is_empty()
+                || maintenance.services.iter().any(|id| id == service_id))
+                && muted(&maintenance.alarms)
+                && maintenance.schedule.is_active(now)
+        }) || self
+            .silences
+            .iter()
+            .any(|silence| silence.expires_at > now && muted(&silence.alarms))
+    }
+
+    /// State of the alarms that aren't silenced, so a silenced alarm isn't announced as active either
+    pub fn notified(
+        &self,
+        alarm_states: &HashMap<AlarmKey, bool>,
+        service_id: &str,
+        now: DateTime<Utc>,
+    ) -> HashMap<AlarmKey, bool> {
+        alarm_states
+            .iter()
+            .filter(|(key, _)| !self.is_silenced(key, service_id, now))
+            .map(|(key, on)| (key.clone(), *on))
+            .collect()
+    }
+
+    /// Removes the transitions of silenced alarms, and re-announces the state of the ones whose silence ended if it
+    /// differs from the last notified
+    pub fn withhold(
+        &mut self,
+        mut alarms: HashMap<AlarmKey, AlarmState>,
+        alarm_states: &HashMap<AlarmKey, bool>,
+        service_id: &str,
+        now: DateTime<Utc>,
+    ) -> HashMap<AlarmKey, AlarmState> {
+        self.silences.retain(|silence| silence.expires_at > now);
+
+        let ended = self
+            .withheld
+            .keys()
+            .filter(|key| !self.is_silenced(key, service_id, now))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in ended {
+            let Some(notified) = self.withheld.remove(&key) else {
+                continue;
+            };
+
+            // Alarms that stopped existing are resolved
+            let state = alarm_states.get(&key).copied().unwrap_or(false);
+            match alarms.get(&key) {
+                Some(transition) if transition.on() == notified => {
+                    let _ = alarms.remove(&key);
+                }
+                Some(_) => {}
+                None if state != notified => {
+                    info!("Silence of {key} ended, announcing its state");
+                    alarms.insert(key.clone(), AlarmState::new(key, state));
+                }
+                None => {}
+            }
+        }
+
+        alarms.retain(|key, transition| {
+            if !self.is_silenced(key, service_id, now) {
+                return true;
+            }
+
+            info!("Withholding the notification of {key}, it's silenced");
+            self.withheld.entry(key.clone()).or_insert(!transition.on());
+            false
+        });
+        alarms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cron, Schedule, SilenceRequest, Silences};
+    use crate::{Alarm, AlarmKey, AlarmState};
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use std::collections::HashMap;
+
+    #[test]
+    fn schedule() {
+        // Sundays at 03:00 for 2 hours
+        let schedule = Schedule::parse("0 3 * * 0", 120).expect("invalid cron");
+        let sunday = |hour, minute| Utc.with_ymd_and_hms(2024, 1, 7, hour, minute, 0).unwrap();
+        assert!(!schedule.is_active(sunday(2, 59)));
+        assert!(schedule.is_active(sunday(3, 0)));
+        assert!(schedule.is_active(sunday(4, 59)));
+        assert!(!schedule.is_active(sunday(5, 0)));
+        assert!(!schedule.is_active(Utc.with_ymd_and_hms(2024, 1, 8, 3, 30, 0).unwrap()));
+
+        let cron = Cron::parse("*/15 9-17 * * 1-5").expect("invalid cron");
+        assert!(cron.matches(Utc.with_ymd_and_hms(2024, 1, 8, 9, 45, 0).unwrap()));
+        assert!(!cron.matches(Utc.with_ymd_and_hms(2024, 1, 8, 9, 50, 0).unwrap()));
+        assert!(!cron.matches(Utc.with_ymd_and_hms(2024, 1, 7, 9, 45, 0).unwrap()));
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("* * * *").is_err());
+
+        let schedule =
+            Schedule::parse("2024-01-01T00:00:00Z/2024-01-01T01:00:00Z", 0).expect("invalid dates");
+        assert!(schedule.is_active(Utc.with_ymd_and_hms(2024, 1, 1, 0, 30, 0).unwrap()));
+        assert!(!schedule.is_active(Utc.with_ymd_and_hms(2024, 1, 1, 1, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn withhold() {
+        let cpu = AlarmKey::from(Alarm::CpuUpperLimitVcpus);
+        let memory = AlarmKey::from(Alarm::MemoryUpperLimitGb);
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let minutes = |minutes| start + TimeDelta::minutes(minutes);
+        let transition =
+            |key: &AlarmKey, on| HashMap::from([(key.clone(), AlarmState::new(key.clone(), on))]);
+
+        let mut silences = Silences::new(Vec::new(), vec![cpu.variable(), memory.variable()]);
+        let request = SilenceRequest {
+            alarms: Vec::new(),
+            reason: None,
+            expires_at: None,
+            duration_minutes: Some(60),
+        };
+        silences.add(request, start).expect("invalid silence");
+        assert_eq!(silences.silences(minutes(59)).len(), 1);
+
+        // Both alarms were notified off, the silenced alarms aren't announced as active
+        let states = HashMap::from([(cpu.clone(), true), (memory.clone(), false)]);
+        assert!(silences
+            .withhold(transition(&cpu, true), &states, "service", minutes(10))
+            .is_empty());
+        assert!(silences
+            .notified(&states, "service", minutes(10))
+            .is_empty());
+
+        // Flips on and back off during the silence, both transitions are dropped
+        assert!(silences
+            .withhold(transition(&memory, true), &states, "service", minutes(20))
+            .is_empty());
+        assert!(silences
+            .withhold(transition(&memory, false), &states, "service", minutes(30))
+            .is_empty());
+
+        // The silence expired, only the alarm whose state differs from the notified one is re-announced
+        let alarms = silences.withhold(HashMap::new(), &states, "service", minutes(60));
+        assert_eq!(alarms.len(), 1);
+        assert!(alarms[&cpu].on());
+        assert!(silences.silences(minutes(60)).is_empty());
+        assert!(silences.silences.is_empty());
+        assert_eq!(silences.notified(&states, "service", minutes(60)), states);
+
+        // Nothing is withheld anymore
+        assert!(silences
+            .withhold(HashMap::new(), &states, "service", minutes(61))
+            .is_empty());
+        assert_eq!(
+            silences
+                .withhold(transition(&cpu, false), &states, "service", minutes(62))
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use crate::{AlarmKey, AlarmState, Error, Result, Usage};
+use chrono::{DateTime, TimeDelta, Utc};
+use derive_get::Getters;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+use strum::Display;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+pub const DEFAULT_HISTORY_RETENTION_HOURS: u32 = 7 * 24;
+/// Transitions are rare, so they are kept for longer than the usage
+pub const DEFAULT_TRANSITIONS_RETENTION_DAYS: u32 = 90;
+
+const TRANSITIONS_FILE: &str = "transitions.jsonl";
+const USAGES_FILE: &str = "usages.jsonl";
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Display)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum StateValue {
+    Ok,
+    Alarm,
+}
+
+impl From<bool> for StateValue {
+    fn from(on: bool) -> Self {
+        if on {
+            Self::Alarm
+        } else {
+            Self::Ok
+        }
+    }
+}
+
+/// Change of state of an alarm, with the measurement that caused it
+#[derive(Getters, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Transition {
+    #[copy]
+    date: DateTime<Utc>,
+    #[serde(flatten)]
+    key: AlarmKey,
+    service_id: String,
+    #[copy]
+    from: StateValue,
+    #[copy]
+    to: StateValue,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[copy]
+    measurement: Option<f64>,
+}
+
+impl Transition {
+    pub fn new(state: &AlarmState, service_id: String, date: DateTime<Utc>) -> Self {
+        Self {
+            date,
+            key: state.key().clone(),
+            service_id,
+            from: StateValue::from(!state.on()),
+            to: StateValue::from(state.on()),
+            measurement: state.measurement(),
+        }
+    }
+}
+
+/// Dates are inclusive, either may be left open
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Range {
+    #[copy]
+    pub from: Option<DateTime<Utc>>,
+    #[copy]
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl Range {
+    pub fn contains(&self, date: DateTime<Utc>) -> bool {
+        self.from.is_none_or(|from| date >= from) && self.to.is_none_or(|to| date <= to)
+    }
+}
+
+/// Transitions and the usage of the service within their retentions, appended to JSON lines files so they survive
+/// restarts
+pub struct Store {
+    transitions: Retained<Transition>,
+    usages: Retained<Usage>,
+}
+
+/// Items of a JSON lines file within the retention, oldest first. The retention is relative to the newest item, so
+/// the history of a service that stopped being monitored isn't wiped once the alarms restart
+struct Retained<T> {
+    path: PathBuf,
+    retention: TimeDelta,
+    date: fn(&T) -> DateTime<Utc>,
+    items: Mutex<VecDeque<T>>,
+    /// Lines of the file past the retention, it's rewritten once they outnumber the retained ones
+    expired: Mutex<usize>,
+}
+
+impl Store {
+    /// Lines that can't be read are skipped, so a corrupted file doesn't stop the alarms
+    pub async fn load(
+        path: PathBuf,
+        retention_hours: u32,
+        transitions_retention_days: u32,
+    ) -> Result<Self> {
+        tokio::fs::create_dir_all(&path)
+            .await
+            .map_err(|err| Error::IoWithMetadata(err, path.display().to_string()))?;
+
+        let transitions = Retained::load(
+            path.join(TRANSITIONS_FILE),
+            TimeDelta::days(transitions_retention_days.into()),
+            Transition::date,
+        )
+        .await?;
+        let usages = Retained::load(
+            path.join(USAGES_FILE),
+            TimeDelta::hours(retention_hours.into()),
+            Usage::end_date,
+        )
+        .await?;
+        info!(
+            "Loaded {} transitions and {} usages from {}",
+            transitions.len(),
+            usages.len(),
+            path.display()
+        );
+
+        Ok(Self {
+            transitions,
+            usages,
+        })
+    }
+
+    pub async fn record(&self, transitions: Vec<Transition>, usages: Vec<Usage>) -> Result<()> {
+        self.transitions.record(transitions).await?;
+        self.usages.record(usages).await
+    }
+
+    /// Oldest first
+    pub fn transitions(&self, range: &Range) -> Vec<Transition> {
+        self.transitions.within(range)
+    }
+
+    /// Oldest first, windows are filtered by their end date
+    pub fn usages(&self, range: &Range) -> Vec<Usage> {
+        self.usages.within(range)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> Retained<T> {
+    async fn load(
+        path: PathBuf,
+        retention: TimeDelta,
+        date: fn(&T) -> DateTime<Utc>,
+    ) -> Result<Self> {
+        let mut items = read::<T>(&path).await?;
+        items.sort_by_key(date);
+        let retained = Self {
+            path,
+            retention,
+            date,
+            items: Mutex::new(items.into()),
+            expired: Mutex::new(0),
+        };
+        retained.expire().await?;
+        Ok(retained)
+    }
+
+    fn len(&self) -> usize {
+        self.items
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    async fn record(&self, items: Vec<T>) -> Result<()> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        append(&self.path, &items).await?;
+        {
+            let mut retained = self.items.lock().unwrap_or_else(PoisonError::into_inner);
+            retained.extend(items);
+            retained.make_contiguous().sort_by_key(self.date);
+        }
+        self.expire().await
+    }
+
+    /// Drops the items past the retention, rewriting the file once most of its lines expired
+    async fn expire(&self) -> Result<()> {
+        let rewrite = {
+            let mut retained = self.items.lock().unwrap_or_else(PoisonError::into_inner);
+            let oldest = retained
+                .back()
+                .map(|item| (self.date)(item) - self.retention);
+            let mut expired = self.expired.lock().unwrap_or_else(PoisonError::into_inner);
+            while retained
+                .front()
+                .zip(oldest)
+                .is_some_and(|(item, oldest)| (self.date)(item) <= oldest)
+            {
+                retained.pop_front();
+                *expired += 1;
+            }
+
+            if *expired > retained.len() {
+                *expired = 0;
+                Some(retained.iter().cloned().collect::<Vec<_>>())
+            } else {
+                None
+            }
+        };
+
+        if let Some(retained) = rewrite {
+            write(&self.path, &retained).await?;
+        }
+        Ok(())
+    }
+
+    fn within(&self, range: &Range) -> Vec<T> {
+        self.items
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|item| range.contains((self.date)(item)))
+            .cloned()
+            .collect()
+    }
+}
+
+async fn read<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let content = match tokio::fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(Error::IoWithMetadata(err, path.display().to_string())),
+    };
+
+    Ok(content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(item) => Some(item),
+            Err(err) => {
+                warn!("Skipping invalid line of {}: {err}", path.display());
+                None
+            }
+        })
+        .collect())
+}
+
+async fn append<T: Serialize>(path: &Path, items: &[T]) -> Result<()> {
+    if items.is_empty() {
+        return Ok(());
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .map_err(|err| Error::IoWithMetadata(err, path.display().to_string()))?;
+    file.write_all(&lines(items)?)
+        .await
+        .map_err(|err| Error::IoWithMetadata(err, path.display().to_string()))
+}
+
+/// Replaces the file atomically, so it's never left half written
+async fn write<T: Serialize>(path: &Path, items: &[T]) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    tokio::fs::write(&temporary, lines(items)?)
+        .await
+        .map_err(|err| Error::IoWithMetadata(err, temporary.display().to_string()))?;
+    tokio::fs::rename(&temporary, path)
+        .await
+        .map_err(|err| Error::IoWithMetadata(err, path.display().to_string()))
+}
+
+fn lines<T: Serialize>(items: &[T]) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for item in items {
+        serde_json::to_writer(&mut bytes, item)?;
+        bytes.push(b'\n');
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Range, StateValue, Store, Transition};
+    use crate::{Alarm, AlarmKey, AlarmState, Usage};
+    use chrono::{TimeDelta, TimeZone, Utc};
+
+    fn usage(hour: i64) -> Usage {
+        let end_date = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap() + TimeDelta::hours(hour);
+        serde_json::from_value(serde_json::json!({
+            "cpu": 1.,
+            "memoryGb": 2.,
+            "diskGb": 3.,
+            "ingressGb": 0.,
+            "egressGb": 0.,
+            "startDate": end_date - TimeDelta::minutes(1),
+            "endDate": end_date,
+        }))
+        .expect("invalid usage")
+    }
+
+    #[tokio::test]
+    async fn record() {
+        let path = std::env::temp_dir().join(format!("alarms-store-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&path).await;
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+        let store = Store::load(path.clone(), 2, 1)
+            .await
+            .expect("unable to load");
+        let state = AlarmState::new(AlarmKey::from(Alarm::CpuUpperLimitVcpus), true)
+            .with_measurement(Some(1.5));
+        let transition = Transition::new(&state, "service".to_owned(), now);
+        assert_eq!(transition.from(), StateValue::Ok);
+        assert_eq!(transition.to(), StateValue::Alarm);
+        let expired = Transition::new(&state, "service".to_owned(), now - TimeDelta::days(2));
+        store
+            .record(vec![expired, transition], (0..5).map(usage).collect())
+            .await
+            .expect("unable to record");
+
+        // Usage and transitions past their retentions are dropped
+        let all = Range::default();
+        assert_eq!(store.transitions(&all).len(), 1);
+        assert_eq!(store.usages(&all).len(), 2);
+        let range = Range {
+            from: Some(now + TimeDelta::hours(4)),
+            to: None,
+        };
+        assert_eq!(store.usages(&range).len(), 1);
+        assert_eq!(store.transitions(&range).len(), 0);
+
+        let store = Store::load(path.clone(), 2, 1)
+            .await
+            .expect("unable to reload");
+        let transitions = store.transitions(&all);
+        assert_eq!(transitions.len(), 1);
+        assert_eq!(transitions[0].measurement(), Some(1.5));
+        assert_eq!(store.usages(&all).len(), 2);
+
+        // A shorter retention applies as soon as the history is loaded
+        let store = Store::load(path.clone(), 1, 1)
+            .await
+            .expect("unable to reload");
+        assert_eq!(store.usages(&all).len(), 1);
+
+        let _ = tokio::fs::remove_dir_all(&path).await;
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{ActionResult, Transition, Usage};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tracing::warn;
+
+/// Updates buffered for each client, the oldest are dropped if it can't keep up
+const CAPACITY: usize = 256;
+
+/// Pushed to the clients of the stream as it happens
+#[derive(Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Update {
+    Transition(Transition),
+    /// Usage of a window of the service, or of one of its replicas
+    Usage(Usage),
+    Action(ActionResult),
+}
+
+impl Update {
+    /// Name of the server-sent event
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Transition(_) => "transition",
+            Self::Usage(_) => "usage",
+            Self::Action(_) => "action",
+        }
+    }
+}
+
+/// Every filter is optional, the alarm only filters transitions and actions
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Filter {
+    pub project: Option<String>,
+    pub service: Option<String>,
+    /// Environment variable of the alarm, like `CPU_UPPER_LIMIT_VCPUS`
+    pub alarm: Option<String>,
+}
+
+/// Broadcasts the updates of the monitored service to every subscriber
+#[derive(Clone)]
+pub struct Updates {
+    project_id: String,
+    service_id: String,
+    sender: broadcast::Sender<Update>,
+}
+
+impl Updates {
+    pub fn new(project_id: String, service_id: String) -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self {
+            project_id,
+            service_id,
+            sender,
+        }
+    }
+
+    /// Dropped if nobody is subscribed
+    pub fn send(&self, update: Update) {
+        let _ = self.sender.send(update);
+    }
+
+    /// Only updates sent after subscribing are received
+    pub fn subscribe(&self, filter: Filter) -> impl Stream<Item = Update> {
+        // Each alarms service monitors a single service, so they either match every update or none
+        let matches = filter
+            .project
+            .as_ref()
+            .is_none_or(|project| *project == self.project_id)
+            && filter
+                .service
+                .as_ref()
+                .is_none_or(|service| *service == self.service_id);
+
+        BroadcastStream::new(self.sender.subscribe()).filter_map(move |update| {
+            let update = match update {
+                Ok(update) => update,
+                Err(err) => {
+                    warn!("Stream subscriber missed updates: {err}");
+                    return None;
+                }
+            };
+
+            let key = match &update {
+                Update::Transition(transition) => Some(transition.key()),
+                Update::Action(action) => Some(action.key()),
+                Update::Usage(_) => None,
+            };
+            let alarm_matches = match (&filter.alarm, key) {
+                (Some(alarm), Some(key)) => key.to_string().eq_ignore_ascii_case(alarm),
+                _ => true,
+            };
+            (matches && alarm_matches).then_some(update)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, Update, Updates};
+    use crate::{Alarm, AlarmKey, AlarmState, Transition};
+    use chrono::{TimeZone, Utc};
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn subscribe() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let updates = Updates::new("project".to_owned(), "service".to_owned());
+        let transition = |alarm| {
+            let state = AlarmState::new(AlarmKey::from(alarm), true);
+            Update::Transition(Transition::new(&state, "service".to_owned(), now))
+        };
+
+        let all = Box::pin(updates.subscribe(Filter::default()));
+        let cpu = Box::pin(updates.subscribe(Filter {
+            service: Some("service".to_owned()),
+            alarm: Some("cpu_upper_limit_vcpus".to_owned()),
+            ..Filter::default()
+        }));
+        let mut other = Box::pin(updates.subscribe(Filter {
+            project: Some("other".to_owned()),
+            ..Filter::default()
+        }));
+
+        updates.send(transition(Alarm::MemoryUpperLimitGb));
+        updates.send(transition(Alarm::CpuUpperLimitVcpus));
+        drop(updates);
+
+        let alarm = |update: Update| match update {
+            Update::Transition(transition) => transition.key().alarm(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            all.map(alarm).collect::<Vec<_>>().await,
+            [Alarm::MemoryUpperLimitGb, Alarm::CpuUpperLimitVcpus]
+        );
+        assert_eq!(
+            cpu.map(alarm).collect::<Vec<_>>().await,
+            [Alarm::CpuUpperLimitVcpus]
+        );
+        assert_eq!(other.next().await.map(alarm), None);
+    }
+}
@@ -0,0 +1,41 @@
+use crate::Clock;
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Clock that only moves when advanced, so each tick runs when the test wants it to
+pub struct FakeClock {
+    now: watch::Sender<DateTime<Utc>>,
+}
+
+impl FakeClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        let (now, _) = watch::channel(now);
+        Self { now }
+    }
+
+    /// Wakes every sleep that ends by the new time
+    pub fn advance(&self, delta: TimeDelta) {
+        self.now.send_modify(|now| *now += delta);
+    }
+}
+
+#[async_trait]
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.borrow()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        let deadline = TimeDelta::from_std(duration)
+            .ok()
+            .and_then(|duration| self.now().checked_add_signed(duration));
+        let Some(deadline) = deadline else {
+            return std::future::pending().await;
+        };
+
+        let mut receiver = self.now.subscribe();
+        let _ = receiver.wait_for(|now| *now >= deadline).await;
+    }
+}
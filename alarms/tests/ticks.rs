@@ -0,0 +1,121 @@
+use alarms::{FakeClock, FakeRailway};
+use axum::{extract::State, routing::post, Json, Router};
+use chrono::{TimeDelta, TimeZone, Utc};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+const SERVICE_ID: &str = "service";
+
+/// Same CPU on every minute the test goes through
+fn usage(cpu: f64) -> serde_json::Value {
+    let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    let values = (-1..3)
+        .map(|minute| {
+            let date = start + TimeDelta::minutes(minute);
+            serde_json::json!({ "ts": date.timestamp(), "value": cpu })
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "metrics": [{
+            "measurement": "CPU_USAGE",
+            "tags": { "deploymentInstanceId": null },
+            "values": values,
+        }],
+    })
+}
+
+async fn notification(receiver: &mut mpsc::Receiver<serde_json::Value>) -> serde_json::Value {
+    tokio::time::timeout(Duration::from_secs(10), receiver.recv())
+        .await
+        .expect("no notification emitted")
+        .expect("webhook stopped")
+}
+
+/// Waits for the tick to fetch the usage, so the clock isn't advanced before it sleeps
+async fn fetched(railway: &FakeRailway, requests: usize) {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while railway.requests("metrics").len() < requests {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("usage not fetched");
+}
+
+#[tokio::test]
+async fn ticks() {
+    // Receives the webhooks emitted
+    let (sender, mut receiver) = mpsc::channel(16);
+    let app = Router::new()
+        .route(
+            "/",
+            post(
+                |State(sender): State<mpsc::Sender<serde_json::Value>>,
+                 Json(body): Json<serde_json::Value>| async move {
+                    let _ = sender.send(body).await;
+                },
+            ),
+        )
+        .with_state(sender);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("unable to bind webhook");
+    let addr = listener
+        .local_addr()
+        .expect("unable to get webhook address");
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    std::env::set_var("RAILWAY_API_TOKEN", "token");
+    std::env::set_var("ALARM_TOKEN", "secret");
+    std::env::set_var("RAILWAY_PROJECT_ID", "project");
+    std::env::set_var("RAILWAY_MONITORED_SERVICE_ID", SERVICE_ID);
+    std::env::set_var("WEB_HOOK_URL", format!("http://{addr}/"));
+    std::env::set_var("PORT", "0");
+    let history_path = std::env::temp_dir().join(format!("alarms-ticks-{}", std::process::id()));
+    std::env::set_var("HISTORY_PATH", &history_path);
+    std::env::set_var("CPU_UPPER_LIMIT_VCPUS", "2");
+    std::env::set_var("DATA_POINTS", "2");
+    std::env::set_var("DATA_POINTS_TO_ALARM", "2");
+
+    let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    let clock = Arc::new(FakeClock::new(start));
+    let railway = Arc::new(FakeRailway::new());
+    railway.respond("metrics", usage(3.));
+    railway.respond("metrics", usage(3.));
+    railway.fail("metrics", 500);
+    railway.respond("metrics", usage(0.5));
+
+    let shutdown = CancellationToken::new();
+    let run = tokio::spawn(alarms::run_with(
+        clock.clone(),
+        railway.clone(),
+        shutdown.clone(),
+    ));
+
+    // Two breaching data points are required to alarm
+    fetched(&railway, 1).await;
+    clock.advance(TimeDelta::minutes(1));
+    let body = notification(&mut receiver).await;
+    assert_eq!(body["serviceId"], SERVICE_ID);
+    assert_eq!(body["alarms"][0]["alarm"], "CPU_UPPER_LIMIT_VCPUS");
+    assert_eq!(body["alarms"][0]["on"], true);
+
+    // The window that failed is backfilled along with the next one in a single query, resolving the alarm
+    clock.advance(TimeDelta::minutes(1));
+    fetched(&railway, 3).await;
+    clock.advance(TimeDelta::minutes(1));
+    let body = notification(&mut receiver).await;
+    assert_eq!(body["alarms"][0]["alarm"], "CPU_UPPER_LIMIT_VCPUS");
+    assert_eq!(body["alarms"][0]["on"], false);
+
+    let requests = railway.requests("metrics");
+    assert_eq!(requests.len(), 4);
+    assert_eq!(requests[3]["startDate"], "2024-01-01T00:01:00Z");
+    assert_eq!(requests[3]["endDate"], "2024-01-01T00:03:00Z");
+    assert_eq!(requests[3]["sampleRateSeconds"], 60);
+
+    shutdown.cancel();
+    run.await.expect("alarms panicked").expect("alarms failed");
+    let _ = std::fs::remove_dir_all(history_path);
+}
@@ -0,0 +1,311 @@
+use crate::{Error, Result};
+use async_trait::async_trait;
+use common::{AlarmConfig, AlarmDefinition, AlarmKey};
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
+use tokio_postgres::{error::SqlState, types::Json, NoTls, Row};
+
+const MAX_CONNECTIONS: usize = 16;
+
+/// The variable is the environment variable that would configure the alarm, so each alarm is defined once per service
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS alarms (
+    id BIGSERIAL PRIMARY KEY,
+    project_id TEXT NOT NULL,
+    service_id TEXT NOT NULL,
+    variable TEXT NOT NULL,
+    key JSONB NOT NULL,
+    config JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (service_id, variable)
+)";
+
+/// Stores the alarms defined for each service, replaceable to test without Postgres
+#[async_trait]
+pub trait Definitions: Send + Sync {
+    /// Oldest first
+    async fn alarms(&self, service_id: &str) -> Result<Vec<AlarmDefinition>>;
+
+    async fn alarm(&self, service_id: &str, id: i64) -> Result<AlarmDefinition>;
+
+    /// Fails with [`Error::AlarmExists`] if the service already defines the alarm
+    async fn create_alarm(
+        &self,
+        project_id: &str,
+        service_id: &str,
+        key: &AlarmKey,
+        config: &AlarmConfig,
+    ) -> Result<AlarmDefinition>;
+
+    async fn update_alarm(
+        &self,
+        service_id: &str,
+        id: i64,
+        key: &AlarmKey,
+        config: &AlarmConfig,
+    ) -> Result<AlarmDefinition>;
+
+    async fn delete_alarm(&self, service_id: &str, id: i64) -> Result<()>;
+}
+
+#[derive(Clone)]
+pub struct Database {
+    pool: Pool,
+}
+
+impl Database {
+    /// Creates the table if it doesn't exist yet
+    pub async fn connect(url: &str) -> Result<Self> {
+        let config = url.parse::<tokio_postgres::Config>()?;
+        let manager = Manager::from_config(
+            config,
+            NoTls,
+            ManagerConfig {
+                recycling_method: RecyclingMethod::Fast,
+            },
+        );
+        let pool = Pool::builder(manager).max_size(MAX_CONNECTIONS).build()?;
+        pool.get().await?.batch_execute(SCHEMA).await?;
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl Definitions for Database {
+    async fn alarms(&self, service_id: &str) -> Result<Vec<AlarmDefinition>> {
+        let rows = self
+            .pool
+            .get()
+            .await?
+            .query(
+                "SELECT * FROM alarms WHERE service_id = $1 ORDER BY id",
+                &[&service_id],
+            )
+            .await?;
+        rows.iter().map(definition).collect()
+    }
+
+    async fn alarm(&self, service_id: &str, id: i64) -> Result<AlarmDefinition> {
+        let row = self
+            .pool
+            .get()
+            .await?
+            .query_opt(
+                "SELECT * FROM alarms WHERE service_id = $1 AND id = $2",
+                &[&service_id, &id],
+            )
+            .await?;
+        definition(&row.ok_or(Error::AlarmNotFound(id))?)
+    }
+
+    async fn create_alarm(
+        &self,
+        project_id: &str,
+        service_id: &str,
+        key: &AlarmKey,
+        config: &AlarmConfig,
+    ) -> Result<AlarmDefinition> {
+        let row = self
+            .pool
+            .get()
+            .await?
+            .query_one(
+                "INSERT INTO alarms (project_id, service_id, variable, key, config)
+                VALUES ($1, $2, $3, $4, $5) RETURNING *",
+                &[
+                    &project_id,
+                    &service_id,
+                    &key.to_string(),
+                    &Json(key),
+                    &Json(config),
+                ],
+            )
+            .await
+            .map_err(|err| conflict(err, key))?;
+        definition(&row)
+    }
+
+    async fn update_alarm(
+        &self,
+        service_id: &str,
+        id: i64,
+        key: &AlarmKey,
+        config: &AlarmConfig,
+    ) -> Result<AlarmDefinition> {
+        let row = self
+            .pool
+            .get()
+            .await?
+            .query_opt(
+                "UPDATE alarms SET variable = $3, key = $4, config = $5, updated_at = now()
+                WHERE service_id = $1 AND id = $2 RETURNING *",
+                &[
+                    &service_id,
+                    &id,
+                    &key.to_string(),
+                    &Json(key),
+                    &Json(config),
+                ],
+            )
+            .await
+            .map_err(|err| conflict(err, key))?;
+        definition(&row.ok_or(Error::AlarmNotFound(id))?)
+    }
+
+    async fn delete_alarm(&self, service_id: &str, id: i64) -> Result<()> {
+        let deleted = self
+            .pool
+            .get()
+            .await?
+            .execute(
+                "DELETE FROM alarms WHERE service_id = $1 AND id = $2",
+                &[&service_id, &id],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(Error::AlarmNotFound(id));
+        }
+        Ok(())
+    }
+}
+
+fn definition(row: &Row) -> Result<AlarmDefinition> {
+    Ok(AlarmDefinition::new(
+        row.try_get("id")?,
+        row.try_get("project_id")?,
+        row.try_get("service_id")?,
+        row.try_get::<_, Json<AlarmKey>>("key")?.0,
+        row.try_get::<_, Json<AlarmConfig>>("config")?.0,
+        row.try_get("created_at")?,
+        row.try_get("updated_at")?,
+    ))
+}
+
+fn conflict(err: tokio_postgres::Error, key: &AlarmKey) -> Error {
+    if err.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+        Error::AlarmExists(key.to_string())
+    } else {
+        err.into()
+    }
+}
+
+/// Definitions kept in memory, with the same constraints as the table
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeDatabase {
+    alarms: std::sync::Mutex<Vec<AlarmDefinition>>,
+}
+
+#[cfg(test)]
+impl FakeDatabase {
+    fn definitions(&self) -> std::sync::MutexGuard<'_, Vec<AlarmDefinition>> {
+        self.alarms
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn check_unique(
+        definitions: &[AlarmDefinition],
+        service_id: &str,
+        id: Option<i64>,
+        key: &AlarmKey,
+    ) -> Result<()> {
+        let exists = definitions.iter().any(|definition| {
+            definition.service_id() == service_id
+                && Some(definition.id()) != id
+                && definition.key().to_string() == key.to_string()
+        });
+        if exists {
+            return Err(Error::AlarmExists(key.to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Definitions for FakeDatabase {
+    async fn alarms(&self, service_id: &str) -> Result<Vec<AlarmDefinition>> {
+        Ok(self
+            .definitions()
+            .iter()
+            .filter(|definition| definition.service_id() == service_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn alarm(&self, service_id: &str, id: i64) -> Result<AlarmDefinition> {
+        self.definitions()
+            .iter()
+            .find(|definition| definition.service_id() == service_id && definition.id() == id)
+            .cloned()
+            .ok_or(Error::AlarmNotFound(id))
+    }
+
+    async fn create_alarm(
+        &self,
+        project_id: &str,
+        service_id: &str,
+        key: &AlarmKey,
+        config: &AlarmConfig,
+    ) -> Result<AlarmDefinition> {
+        let mut definitions = self.definitions();
+        Self::check_unique(&definitions, service_id, None, key)?;
+        let id = definitions
+            .iter()
+            .map(AlarmDefinition::id)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let now = chrono::Utc::now();
+        let definition = AlarmDefinition::new(
+            id,
+            project_id.to_owned(),
+            service_id.to_owned(),
+            key.clone(),
+            config.clone(),
+            now,
+            now,
+        );
+        definitions.push(definition.clone());
+        Ok(definition)
+    }
+
+    async fn update_alarm(
+        &self,
+        service_id: &str,
+        id: i64,
+        key: &AlarmKey,
+        config: &AlarmConfig,
+    ) -> Result<AlarmDefinition> {
+        let mut definitions = self.definitions();
+        Self::check_unique(&definitions, service_id, Some(id), key)?;
+        let index = definitions
+            .iter()
+            .position(|definition| definition.service_id() == service_id && definition.id() == id)
+            .ok_or(Error::AlarmNotFound(id))?;
+        let previous = &definitions[index];
+        let definition = AlarmDefinition::new(
+            id,
+            previous.project_id().clone(),
+            service_id.to_owned(),
+            key.clone(),
+            config.clone(),
+            previous.created_at(),
+            chrono::Utc::now(),
+        );
+        definitions[index] = definition.clone();
+        Ok(definition)
+    }
+
+    async fn delete_alarm(&self, service_id: &str, id: i64) -> Result<()> {
+        let mut definitions = self.definitions();
+        let count = definitions.len();
+        definitions
+            .retain(|definition| definition.service_id() != service_id || definition.id() != id);
+        if definitions.len() == count {
+            return Err(Error::AlarmNotFound(id));
+        }
+        Ok(())
+    }
+}
@@ -1,25 +1,54 @@
-mod railway;
+mod database;
 
-use railway::Railway;
+pub use common::{Project, ProjectService, RailwayClient};
+pub use database::{Database, Definitions};
 
 use axum::{
+    extract::{FromRef, Path, State},
     http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    routing::post,
+    routing::{get, post},
     Json, Router,
 };
+use common::{Alarm, AlarmConfig, AlarmDefinition, AlarmKey};
 use derive_get::Getters;
-use serde::{Serialize, Deserialize};
-use std::net::SocketAddr;
+use serde::Deserialize;
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 use tracing::{error, info};
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-static PROJECTS: &str = include_str!("graphql/projects.gql");
-static SERVICES: &str = include_str!("graphql/services.gql");
+#[derive(Clone)]
+pub struct AppState {
+    pub railway: RailwayClient,
+    pub database: Arc<dyn Definitions>,
+}
+
+impl FromRef<AppState> for RailwayClient {
+    fn from_ref(state: &AppState) -> Self {
+        state.railway.clone()
+    }
+}
 
-pub fn router() -> Router {
-    Router::new().route("/v1/projects", post(projects)).route("/v1/services", post(services))
+impl FromRef<AppState> for Arc<dyn Definitions> {
+    fn from_ref(state: &AppState) -> Self {
+        Arc::clone(&state.database)
+    }
+}
+
+pub fn router(railway: RailwayClient, database: Arc<dyn Definitions>) -> Router {
+    Router::new()
+        .route("/v1/projects", post(projects))
+        .route("/v1/services", post(services))
+        .route(
+            "/v1/projects/:project_id/services/:service_id/alarms",
+            get(alarms).post(create_alarm),
+        )
+        .route(
+            "/v1/projects/:project_id/services/:service_id/alarms/:id",
+            get(alarm).put(update_alarm).delete(delete_alarm),
+        )
+        .with_state(AppState { railway, database })
 }
 
 pub async fn serve(app: Router, port: u16) -> color_eyre::Result<()> {
@@ -29,79 +58,148 @@ pub async fn serve(app: Router, port: u16) -> color_eyre::Result<()> {
     Ok(axum::serve(listener, app).await?)
 }
 
-#[derive(Getters, Debug, Clone, Serialize)]
+pub async fn projects(
+    State(railway): State<RailwayClient>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Project>>> {
+    let token = token(&headers)?;
+    Ok(Json(Project::list(&railway.with_token(token)).await?))
+}
+
+#[derive(Getters, Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct Project {
-    id: String,
-    name: String,
+pub struct ServicesRequest {
+    project_id: String,
 }
 
-pub async fn projects(headers: HeaderMap) -> Result<Json<Vec<Project>>> {
-    let mut token = headers
-        .get("Authorization")
-        .ok_or(Error::AuthorizationMissing)?
-        .to_str()?
-        .to_owned();
-    if token.starts_with("Bearer ") {
-        token.drain(.."Bearer ".len());
-    } else {
-        return Err(Error::AuthorizationMissing);
-    }
+pub async fn services(
+    State(railway): State<RailwayClient>,
+    headers: HeaderMap,
+    Json(req): Json<ServicesRequest>,
+) -> Result<Json<Vec<ProjectService>>> {
+    let token = token(&headers)?;
+    Ok(Json(
+        Project::services(&railway.with_token(token), &req.project_id).await?,
+    ))
+}
 
-    #[derive(Deserialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct ProjectsRailwayResponseEdgesEdgeNode {
-        id: String,
-        name: String,
-    }
+#[derive(Getters, Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlarmRequest {
+    #[copy]
+    alarm: Alarm,
+    /// Required by the named alarms, like `ERRORS` for `LOG_MATCHED_ERRORS`
+    name: Option<String>,
+    config: AlarmConfig,
+}
 
-    #[derive(Deserialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct ProjectsRailwayResponseEdgesEdge {
-        node: ProjectsRailwayResponseEdgesEdgeNode
-    }
+impl AlarmRequest {
+    /// Same rules as the environment variables, composites are validated against the other alarms of the service
+    fn validate(&self, definitions: &[AlarmDefinition], replaced: Option<i64>) -> Result<AlarmKey> {
+        let key = AlarmKey::new(self.alarm, self.name.clone(), None);
+        let _ = self.config.check(&key)?;
 
-    #[derive(Deserialize, Debug, Default)]
-    #[serde(rename_all = "camelCase")]
-    struct ProjectsRailwayResponseEdges {
-        edges: Vec<ProjectsRailwayResponseEdgesEdge>,
+        let mut configs: HashMap<_, _> = definitions
+            .iter()
+            .filter(|definition| Some(definition.id()) != replaced)
+            .map(|definition| (definition.key().clone(), definition.config().clone()))
+            .collect();
+        configs.insert(key.clone(), self.config.clone());
+        AlarmConfig::validate_composites(&configs)?;
+        Ok(key)
     }
+}
 
-    #[derive(Deserialize, Debug, Default)]
-    #[serde(rename_all = "camelCase")]
-    struct ProjectsRailwayResponse {
-        projects: ProjectsRailwayResponseEdges,
-    }
+pub async fn alarms(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((project_id, service_id)): Path<(String, String)>,
+) -> Result<Json<Vec<AlarmDefinition>>> {
+    authorize(&state.railway, &headers, &project_id, &service_id).await?;
+    Ok(Json(state.database.alarms(&service_id).await?))
+}
 
-    let response: ProjectsRailwayResponse = Railway::query(
-        serde_json::json!({ "query": PROJECTS }),
-        &token,
-    )
-    .await?;
-    
-    let mut projects = Vec::with_capacity(response.projects.edges.len());
-    for project in response.projects.edges {
-        projects.push(Project { id: project.node.id, name: project.node.name });
-    }
+pub async fn alarm(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((project_id, service_id, id)): Path<(String, String, i64)>,
+) -> Result<Json<AlarmDefinition>> {
+    authorize(&state.railway, &headers, &project_id, &service_id).await?;
+    Ok(Json(state.database.alarm(&service_id, id).await?))
+}
 
-    Ok(Json(projects))
+pub async fn create_alarm(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((project_id, service_id)): Path<(String, String)>,
+    Json(req): Json<AlarmRequest>,
+) -> Result<(StatusCode, Json<AlarmDefinition>)> {
+    authorize(&state.railway, &headers, &project_id, &service_id).await?;
+    let key = req.validate(&state.database.alarms(&service_id).await?, None)?;
+    let definition = state
+        .database
+        .create_alarm(&project_id, &service_id, &key, &req.config)
+        .await?;
+    info!("Alarm {key} of service {service_id} created");
+    Ok((StatusCode::CREATED, Json(definition)))
 }
 
-#[derive(Getters, Debug, Clone, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ServicesRequest {
-    project_id: String,
+pub async fn update_alarm(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((project_id, service_id, id)): Path<(String, String, i64)>,
+    Json(req): Json<AlarmRequest>,
+) -> Result<Json<AlarmDefinition>> {
+    authorize(&state.railway, &headers, &project_id, &service_id).await?;
+    let key = req.validate(&state.database.alarms(&service_id).await?, Some(id))?;
+    let definition = state
+        .database
+        .update_alarm(&service_id, id, &key, &req.config)
+        .await?;
+    info!("Alarm {key} of service {service_id} updated");
+    Ok(Json(definition))
 }
 
-#[derive(Getters, Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Service {
-    id: String,
-    name: String,
-    health_check_url: Option<String>,
+pub async fn delete_alarm(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((project_id, service_id, id)): Path<(String, String, i64)>,
+) -> Result<StatusCode> {
+    authorize(&state.railway, &headers, &project_id, &service_id).await?;
+
+    // Composites can't be left referring to the deleted alarm
+    let remaining = state
+        .database
+        .alarms(&service_id)
+        .await?
+        .into_iter()
+        .filter(|definition| definition.id() != id)
+        .map(|definition| (definition.key().clone(), definition.config().clone()))
+        .collect();
+    AlarmConfig::validate_composites(&remaining)?;
+
+    state.database.delete_alarm(&service_id, id).await?;
+    info!("Alarm {id} of service {service_id} deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Alarms can only be managed with a token that has access to the service
+async fn authorize(
+    railway: &RailwayClient,
+    headers: &HeaderMap,
+    project_id: &str,
+    service_id: &str,
+) -> Result<()> {
+    let token = token(headers)?;
+    let services = Project::services(&railway.with_token(token), project_id).await?;
+    if services.iter().any(|service| service.id() == service_id) {
+        Ok(())
+    } else {
+        Err(Error::ServiceNotFound(service_id.to_owned()))
+    }
 }
 
-pub async fn services(headers: HeaderMap, Json(req): Json<ServicesRequest>) -> Result<Json<Vec<Service>>> {
+fn token(headers: &HeaderMap) -> Result<String> {
     let mut token = headers
         .get("Authorization")
         .ok_or(Error::AuthorizationMissing)?
@@ -109,75 +207,10 @@ pub async fn services(headers: HeaderMap, Json(req): Json<ServicesRequest>) -> R
         .to_owned();
     if token.starts_with("Bearer ") {
         token.drain(.."Bearer ".len());
+        Ok(token)
     } else {
-        return Err(Error::AuthorizationMissing);
+        Err(Error::AuthorizationMissing)
     }
-
-    #[derive(Deserialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct ServicesRailwayResponseProjectServicesEdgeNodeInstancesEdgesNode {
-        healthcheck_path: Option<String>,
-    }
-
-    #[derive(Deserialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct ServicesRailwayResponseProjectServicesEdgeNodeInstancesEdges {
-        node: ServicesRailwayResponseProjectServicesEdgeNodeInstancesEdgesNode
-    }
-
-    #[derive(Deserialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct ServicesRailwayResponseProjectServicesEdgeNodeInstances {
-        edges: Vec<ServicesRailwayResponseProjectServicesEdgeNodeInstancesEdges>,
-    }
-
-    #[derive(Deserialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct ServicesRailwayResponseProjectServicesEdgeNode {
-        id: String,
-        name: String,
-        service_instances: ServicesRailwayResponseProjectServicesEdgeNodeInstances,
-    }
-
-    #[derive(Deserialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct ServicesRailwayResponseProjectServicesEdge {
-        node: ServicesRailwayResponseProjectServicesEdgeNode
-    }
-
-    #[derive(Deserialize, Debug, Default)]
-    #[serde(rename_all = "camelCase")]
-    struct ServicesRailwayResponseProjectServices {
-        edges: Vec<ServicesRailwayResponseProjectServicesEdge>,
-    }
-
-    #[derive(Deserialize, Debug, Default)]
-    #[serde(rename_all = "camelCase")]
-    struct ServicesRailwayResponseProject {
-        services: ServicesRailwayResponseProjectServices,
-    }
-
-    #[derive(Deserialize, Debug, Default)]
-    #[serde(rename_all = "camelCase")]
-    struct ServicesRailwayResponse {
-        project: ServicesRailwayResponseProject,
-    }
-
-    let response: ServicesRailwayResponse = Railway::query(
-        serde_json::json!({ "query": SERVICES, "variables": { "projectId": req.project_id } }),
-        &token,
-    )
-    .await?;
-    
-    let mut services = Vec::with_capacity(response.project.services.edges.len());
-    for service in response.project.services.edges {
-        let health_check_url = service.node.service_instances.edges.iter().find(|i| i.node.healthcheck_path.is_some()).and_then(|i| i.node.healthcheck_path.to_owned());
-        services.push(Service { id: service.node.id, name: service.node.name,
-            health_check_url,
-        });
-    }
-
-    Ok(Json(services))
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -187,21 +220,40 @@ pub enum Error {
     #[error("authorization missing")]
     AuthorizationMissing,
     #[error(transparent)]
-    Reqwest(#[from] reqwest::Error),
-    #[error("railway responded with: {0:?}")]
-    Railway(Vec<String>),
-    #[error("railway data missing: {0}")]
-    RailwayDataMissing(&'static str),
-    #[error("railway request failed with status {0}: {1}")]
-    RailwayStatusFailure(u16, String),
+    Common(#[from] common::Error),
+    #[error("service {0} not found")]
+    ServiceNotFound(String),
+    #[error("alarm {0} not found")]
+    AlarmNotFound(i64),
+    #[error("alarm {0} already exists")]
+    AlarmExists(String),
+    #[error(transparent)]
+    Postgres(#[from] tokio_postgres::Error),
     #[error(transparent)]
-    SerdeJson(#[from] serde_json::Error),
+    Pool(#[from] deadpool_postgres::PoolError),
+    #[error(transparent)]
+    BuildPool(#[from] deadpool_postgres::BuildError),
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         let (status, error_message) = match self {
             Error::AuthorizationMissing => (StatusCode::UNAUTHORIZED, "Unauthorized".to_owned()),
+            Error::ServiceNotFound(_) | Error::AlarmNotFound(_) => {
+                (StatusCode::NOT_FOUND, self.to_string())
+            }
+            Error::AlarmExists(_) => (StatusCode::CONFLICT, self.to_string()),
+            Error::Common(
+                common::Error::InvalidAction(..)
+                | common::Error::InvalidAlarmName(_)
+                | common::Error::InvalidExpression(..)
+                | common::Error::InvalidSetting(..)
+                | common::Error::InvalidStatistic(_)
+                | common::Error::ParseBoolWithMetadata(..)
+                | common::Error::ParseFloatWithMetadata(..)
+                | common::Error::RegexWithMetadata(..)
+                | common::Error::UnknownVariable(..),
+            ) => (StatusCode::BAD_REQUEST, self.to_string()),
             // Internal failures
             err => {
                 error!("{err})");
@@ -219,3 +271,99 @@ impl IntoResponse for Error {
         (status, body).into_response()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{create_alarm, database::FakeDatabase, delete_alarm, AlarmRequest, AppState};
+    use axum::{
+        extract::{Path, State},
+        http::{HeaderMap, StatusCode},
+        response::IntoResponse,
+        Json,
+    };
+    use common::{FakeRailway, RailwayClient};
+    use std::sync::Arc;
+
+    fn state() -> AppState {
+        let railway = FakeRailway::new();
+        railway.respond(
+            "services",
+            serde_json::json!({
+                "project": {
+                    "services": {
+                        "edges": [{
+                            "node": {
+                                "id": "service",
+                                "name": "api",
+                                "serviceInstances": { "edges": [] },
+                            },
+                        }],
+                        "pageInfo": { "hasNextPage": false, "endCursor": null },
+                    },
+                },
+            }),
+        );
+        AppState {
+            railway: RailwayClient::with_transport(Arc::new(railway)),
+            database: Arc::new(FakeDatabase::default()),
+        }
+    }
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", "Bearer token".parse().unwrap());
+        headers
+    }
+
+    fn path() -> Path<(String, String)> {
+        Path(("project".to_owned(), "service".to_owned()))
+    }
+
+    async fn create(state: &AppState, request: serde_json::Value) -> StatusCode {
+        let request = serde_json::from_value::<AlarmRequest>(request).expect("invalid request");
+        create_alarm(State(state.clone()), headers(), path(), Json(request))
+            .await
+            .into_response()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn alarms() {
+        let state = state();
+        let cpu = serde_json::json!({
+            "alarm": "CPU_UPPER_LIMIT_VCPUS",
+            "config": { "value": "2" },
+        });
+
+        // Validated like the environment variables
+        let invalid = serde_json::json!({
+            "alarm": "CPU_UPPER_LIMIT_VCPUS",
+            "config": { "value": "2", "periodSecs": 15 },
+        });
+        assert_eq!(create(&state, invalid).await, StatusCode::BAD_REQUEST);
+
+        assert_eq!(create(&state, cpu.clone()).await, StatusCode::CREATED);
+        assert_eq!(create(&state, cpu).await, StatusCode::CONFLICT);
+
+        let composite = serde_json::json!({
+            "alarm": "COMPOSITE",
+            "name": "BUSY",
+            "config": { "value": "CpuUpperLimitVcpus" },
+        });
+        assert_eq!(create(&state, composite).await, StatusCode::CREATED);
+
+        // The composite can't be left referring to a deleted alarm
+        let alarms = state.database.alarms("service").await.unwrap();
+        let id = alarms[0].id();
+        let status = delete_alarm(
+            State(state.clone()),
+            headers(),
+            Path(("project".to_owned(), "service".to_owned(), id)),
+        )
+        .await
+        .into_response()
+        .status();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(state.database.alarms("service").await.unwrap().len(), 2);
+    }
+}
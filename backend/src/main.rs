@@ -1,6 +1,7 @@
-use backend::{router, serve};
+use backend::{router, serve, Database, RailwayClient};
 
 use axum::http::{HeaderValue, HeaderName, Method};
+use std::sync::Arc;
 use tower_http::cors::CorsLayer;
 use tracing_subscriber::prelude::*;
 
@@ -41,14 +42,18 @@ async fn main() -> color_eyre::Result<()> {
         .unwrap_or_else(|_| "4000".to_owned())
         .parse::<u16>()?;
 
-    let app = router().layer(
+    let database_url = std::env::var("DATABASE_URL")
+        .map_err(|_| color_eyre::eyre::eyre!("DATABASE_URL is required to store the alarms"))?;
+    let database = Database::connect(&database_url).await?;
+
+    let app = router(RailwayClient::new()?, Arc::new(database)).layer(
         CorsLayer::new()
             .allow_credentials(false)
             .allow_headers(vec![
                 HeaderName::from_static("authorization"),
                 HeaderName::from_static("content-type"),
             ])
-            .allow_methods([Method::POST])
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
             .allow_origin(origin)
     );
     serve(app, port).await?;
@@ -0,0 +1,166 @@
+#!/usr/bin/env python3
+"""Regenerates src/graphql/schema.graphql from the introspection of Railway's GraphQL API.
+
+The schema keeps the part of the API reached by the operations in src/graphql: the fields they select, with the
+arguments, input types and enums those fields need. graphql_client checks the operations against it at build time.
+
+    RAILWAY_API_TOKEN=... python3 common/schema.py           # rewrites the schema
+    RAILWAY_API_TOKEN=... python3 common/schema.py --check   # fails if the vendored schema drifted from the API
+
+Requires graphql-core (`pip install graphql-core`). `RAILWAY_API_URL` points to another instance of the API, and
+`--introspection FILE` reads a saved introspection result instead of querying the API.
+"""
+
+import argparse
+import difflib
+import json
+import os
+import sys
+import urllib.request
+from pathlib import Path
+
+from graphql import (
+    DocumentNode,
+    EnumTypeDefinitionNode,
+    InputObjectTypeDefinitionNode,
+    InterfaceTypeDefinitionNode,
+    ObjectTypeDefinitionNode,
+    ScalarTypeDefinitionNode,
+    SchemaDefinitionNode,
+    TypeInfo,
+    TypeInfoVisitor,
+    UnionTypeDefinitionNode,
+    Visitor,
+    build_client_schema,
+    get_introspection_query,
+    get_named_type,
+    is_input_object_type,
+    lexicographic_sort_schema,
+    parse,
+    print_ast,
+    print_schema,
+    visit,
+)
+
+GRAPHQL = Path(__file__).resolve().parent / "src" / "graphql"
+SCHEMA = GRAPHQL / "schema.graphql"
+
+HEADER = """\
+# Generated by common/schema.py from the introspection of Railway's GraphQL API, do not edit by hand.
+# Only the part of the API reached by the operations in this directory is kept, run the script again after changing
+# them.
+"""
+
+
+def introspect(path):
+    if path is not None:
+        result = json.loads(Path(path).read_text())
+    else:
+        token = os.environ.get("RAILWAY_API_TOKEN")
+        if not token:
+            sys.exit("RAILWAY_API_TOKEN must be set to introspect Railway's API")
+        url = os.environ.get("RAILWAY_API_URL", "https://backboard.railway.app")
+        request = urllib.request.Request(
+            f"{url}/graphql/v2",
+            data=json.dumps({"query": get_introspection_query()}).encode(),
+            headers={"Authorization": f"Bearer {token}", "Content-Type": "application/json"},
+        )
+        with urllib.request.urlopen(request, timeout=60) as response:
+            result = json.load(response)
+    if result.get("errors"):
+        sys.exit(f"Introspection failed: {result['errors']}")
+    return build_client_schema(result.get("data", result))
+
+
+class Reached(Visitor):
+    """Collects the output fields selected by the operations, and every type their arguments and variables need"""
+
+    def __init__(self, type_info, fields, types):
+        super().__init__()
+        self.type_info = type_info
+        self.fields = fields
+        self.types = types
+
+    def enter_field(self, node, *_):
+        parent = self.type_info.get_parent_type()
+        field = self.type_info.get_field_def()
+        if parent is None or field is None or node.name.value.startswith("__"):
+            return
+        self.fields.setdefault(parent.name, set()).add(node.name.value)
+        self.types.add(parent.name)
+        self.types.add(get_named_type(field.type).name)
+        for argument in field.args.values():
+            reach_input(argument.type, self.types)
+
+    def enter_inline_fragment(self, *_):
+        self.types.add(get_named_type(self.type_info.get_type()).name)
+
+    def enter_variable_definition(self, *_):
+        reach_input(self.type_info.get_input_type(), self.types)
+
+
+def reach_input(input_type, types):
+    named = get_named_type(input_type)
+    if named is None or named.name in types:
+        return
+    types.add(named.name)
+    if is_input_object_type(named):
+        for field in named.fields.values():
+            reach_input(field.type, types)
+
+
+def prune(schema):
+    fields, types = {}, set()
+    type_info = TypeInfo(schema)
+    for operation in sorted(GRAPHQL.glob("*.gql")):
+        visit(parse(operation.read_text()), TypeInfoVisitor(type_info, Reached(type_info, fields, types)))
+
+    kept = []
+    for definition in parse(print_schema(lexicographic_sort_schema(schema))).definitions:
+        if isinstance(definition, SchemaDefinitionNode):
+            kept.append(definition)
+            continue
+        name = getattr(definition, "name", None)
+        if name is None or name.value not in types:
+            continue
+        if isinstance(definition, (ObjectTypeDefinitionNode, InterfaceTypeDefinitionNode)):
+            selected = fields.get(name.value, set())
+            definition.fields = tuple(field for field in definition.fields if field.name.value in selected)
+            definition.interfaces = tuple(
+                interface for interface in definition.interfaces if interface.name.value in types
+            )
+        elif isinstance(definition, UnionTypeDefinitionNode):
+            definition.types = tuple(member for member in definition.types if member.name.value in types)
+        elif not isinstance(
+            definition, (ScalarTypeDefinitionNode, EnumTypeDefinitionNode, InputObjectTypeDefinitionNode)
+        ):
+            continue
+        kept.append(definition)
+    return HEADER + "\n" + print_ast(DocumentNode(definitions=tuple(kept))) + "\n"
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__, formatter_class=argparse.RawDescriptionHelpFormatter)
+    parser.add_argument("--check", action="store_true", help="fail instead of rewriting when the schema is outdated")
+    parser.add_argument("--introspection", metavar="FILE", help="introspection result to read instead of the API")
+    args = parser.parse_args()
+
+    generated = prune(introspect(args.introspection))
+    if not args.check:
+        SCHEMA.write_text(generated)
+        return
+    vendored = SCHEMA.read_text()
+    if vendored != generated:
+        sys.stdout.writelines(
+            difflib.unified_diff(
+                vendored.splitlines(keepends=True),
+                generated.splitlines(keepends=True),
+                "vendored/schema.graphql",
+                "introspected/schema.graphql",
+            )
+        )
+        sys.exit("src/graphql/schema.graphql is outdated, run common/schema.py to regenerate it")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,161 @@
+pub mod action;
+mod expression;
+mod statistic;
+
+pub use action::{Action, ActionKind};
+pub use expression::{Expression, Function, Operator};
+pub use statistic::Statistic;
+
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter};
+
+#[remain::sorted]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash, Display, EnumIter)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum Alarm {
+    Anomaly,
+    Composite,
+    CostUpperLimit,
+    CpuLowerLimitPercent,
+    CpuLowerLimitVcpus,
+    CpuUpperLimitPercent,
+    CpuUpperLimitVcpus,
+    DeploymentCrashed,
+    DeploymentFailed,
+    DeploymentRemoved,
+    DeploymentRestartLoop,
+    DiskForecastUpperLimitGb,
+    DiskGrowthUpperLimitGbPerHour,
+    DiskLowerLimitGb,
+    DiskUpperLimitGb,
+    EgressLowerLimitGb,
+    EgressUpperLimitGb,
+    Expression,
+    HealthCheckFailed,
+    IngressLowerLimitGb,
+    IngressUpperLimitGb,
+    LogMatched,
+    MemoryForecastUpperLimitGb,
+    MemoryGrowthUpperLimitGbPerHour,
+    MemoryLowerLimitGb,
+    MemoryLowerLimitPercent,
+    MemoryUpperLimitGb,
+    MemoryUpperLimitPercent,
+}
+
+impl Alarm {
+    /// Named alarms can be configured many times, each with its own name: `LOG_MATCHED_ERRORS`, `LOG_MATCHED_OOM`
+    pub fn is_named(self) -> bool {
+        matches!(
+            self,
+            Self::Anomaly | Self::Composite | Self::Expression | Self::LogMatched
+        )
+    }
+
+    /// Evaluated from the trend of the retained history instead of the samples of each period
+    pub fn is_trend(self) -> bool {
+        matches!(
+            self,
+            Self::DiskForecastUpperLimitGb
+                | Self::DiskGrowthUpperLimitGbPerHour
+                | Self::MemoryForecastUpperLimitGb
+                | Self::MemoryGrowthUpperLimitGbPerHour
+        )
+    }
+
+    /// Thresholds are a percentage of the limits of the service instances
+    pub fn is_percentage(self) -> bool {
+        matches!(
+            self,
+            Self::CpuLowerLimitPercent
+                | Self::CpuUpperLimitPercent
+                | Self::MemoryLowerLimitPercent
+                | Self::MemoryUpperLimitPercent
+        )
+    }
+}
+
+/// Identifies an alarm being evaluated, alarms configured per replica will have one key for each replica
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AlarmKey {
+    #[copy]
+    alarm: Alarm,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replica: Option<String>,
+}
+
+impl AlarmKey {
+    pub fn new(alarm: Alarm, name: Option<String>, replica: Option<String>) -> Self {
+        Self {
+            alarm,
+            name,
+            replica,
+        }
+    }
+
+    pub fn named(alarm: Alarm, name: String) -> Self {
+        Self::new(alarm, Some(name), None)
+    }
+
+    pub fn with_replica(&self, replica: String) -> Self {
+        Self::new(self.alarm, self.name.clone(), Some(replica))
+    }
+
+    /// Name used to refer to the alarm in composite alarms, like `cpu_upper_limit_vcpus`
+    pub fn variable(&self) -> String {
+        self.to_string().to_lowercase()
+    }
+}
+
+impl From<Alarm> for AlarmKey {
+    fn from(alarm: Alarm) -> Self {
+        Self::new(alarm, None, None)
+    }
+}
+
+/// Formats the key as the environment variable that configures it
+impl std::fmt::Display for AlarmKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{}_{name}", self.alarm),
+            None => write!(f, "{}", self.alarm),
+        }
+    }
+}
+
+#[derive(Getters, Serialize, Deserialize, Clone, Debug)]
+pub struct AlarmState {
+    #[serde(flatten)]
+    key: AlarmKey,
+    #[copy]
+    on: bool,
+    /// Value the alarm evaluated when it changed state, like the statistic compared to the threshold
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[copy]
+    measurement: Option<f64>,
+}
+
+impl AlarmState {
+    pub fn new(key: AlarmKey, on: bool) -> Self {
+        Self {
+            key,
+            on,
+            measurement: None,
+        }
+    }
+
+    pub fn with_measurement(self, measurement: Option<f64>) -> Self {
+        Self {
+            measurement,
+            ..self
+        }
+    }
+
+    pub fn alarm(&self) -> Alarm {
+        self.key.alarm()
+    }
+}
@@ -0,0 +1,94 @@
+use crate::Error;
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+pub const DEFAULT_ACTION_COOLDOWN_MINUTES: u32 = 30;
+pub const DEFAULT_ACTION_MAX_EXECUTIONS: u16 = 3;
+pub const MIN_ACTION_MAX_EXECUTIONS: u16 = 1;
+pub const DEFAULT_ACTION_WINDOW_MINUTES: u32 = 24 * 60;
+pub const DEFAULT_ACTION_DRY_RUN: bool = false;
+
+/// Railway mutation run on the monitored service
+#[remain::sorted]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash, Display, EnumIter)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum ActionKind {
+    /// Builds and deploys the latest deployment again
+    Redeploy,
+    /// Sets the number of replicas of the service
+    Replicas,
+    /// Restarts the instances of the latest deployment
+    Restart,
+    /// Stops the latest deployment
+    Stop,
+}
+
+impl FromStr for ActionKind {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::iter()
+            .find(|kind| kind.to_string().eq_ignore_ascii_case(value.trim()))
+            .ok_or_else(|| {
+                let kinds = Self::iter()
+                    .map(|kind| kind.to_string())
+                    .collect::<Vec<_>>();
+                Error::InvalidAction(value.to_owned(), kinds.join(", "))
+            })
+    }
+}
+
+/// Runs when the alarm enters the ALARM state, limited so a flapping alarm can't keep acting on the service
+#[derive(Getters, Serialize, Deserialize, Copy, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Action {
+    #[copy]
+    pub kind: ActionKind,
+    /// Replicas the service is set to, required by `REPLICAS`
+    #[serde(default)]
+    #[copy]
+    pub replicas: Option<u16>,
+    /// Minutes after an execution in which the action doesn't run again
+    #[serde(default = "default_cooldown_minutes")]
+    #[copy]
+    pub cooldown_minutes: u32,
+    /// Executions allowed within the last `window_minutes`
+    #[serde(default = "default_max_executions")]
+    #[copy]
+    pub max_executions: u16,
+    #[serde(default = "default_window_minutes")]
+    #[copy]
+    pub window_minutes: u32,
+    /// Records and notifies what would have been done, without changing the service
+    #[serde(default)]
+    #[copy]
+    pub dry_run: bool,
+}
+
+impl Action {
+    pub fn new(kind: ActionKind) -> Self {
+        Self {
+            kind,
+            replicas: None,
+            cooldown_minutes: DEFAULT_ACTION_COOLDOWN_MINUTES,
+            max_executions: DEFAULT_ACTION_MAX_EXECUTIONS,
+            window_minutes: DEFAULT_ACTION_WINDOW_MINUTES,
+            dry_run: DEFAULT_ACTION_DRY_RUN,
+        }
+    }
+}
+
+fn default_cooldown_minutes() -> u32 {
+    DEFAULT_ACTION_COOLDOWN_MINUTES
+}
+
+fn default_max_executions() -> u16 {
+    DEFAULT_ACTION_MAX_EXECUTIONS
+}
+
+fn default_window_minutes() -> u32 {
+    DEFAULT_ACTION_WINDOW_MINUTES
+}
@@ -0,0 +1,461 @@
+use crate::{Error, Result};
+
+/// Math expression over metrics, like `egress_gb - ingress_gb > 10` or `cpu > 2 && memory_gb > 4`
+///
+/// Comparisons and logical operators evaluate to `1` or `0`, the expression breaches if it evaluates to anything but `0`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    Binary(Box<Expression>, Operator, Box<Expression>),
+    Call(Function, Vec<Expression>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Function {
+    /// True if all arguments are true
+    AllOf,
+    /// True if at least one argument is true
+    AnyOf,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Identifier(String),
+    Operator(Operator),
+    Bang,
+    Comma,
+    OpenParen,
+    CloseParen,
+}
+
+impl Expression {
+    /// Parses the expression, all identifiers must be part of `variables`
+    pub fn parse(source: &str, variables: &[&str]) -> Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            source,
+            tokens,
+            position: 0,
+            depth: 0,
+        };
+        let expression = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(parser.error(&format!("unexpected {token:?}")));
+        }
+
+        if let Some(variable) = expression
+            .variables()
+            .into_iter()
+            .find(|variable| !variables.contains(variable))
+        {
+            return Err(Error::InvalidExpression(
+                source.to_owned(),
+                format!(
+                    "unknown variable {variable}, expected one of: {}",
+                    variables.join(", ")
+                ),
+            ));
+        }
+
+        Ok(expression)
+    }
+
+    /// Returns `None` if a variable has no value
+    pub fn evaluate(&self, variable: &impl Fn(&str) -> Option<f64>) -> Option<f64> {
+        let value = match self {
+            Self::Number(number) => *number,
+            Self::Variable(name) => variable(name)?,
+            Self::Negate(expression) => -expression.evaluate(variable)?,
+            Self::Not(expression) => bool_to_f64(expression.evaluate(variable)? == 0.),
+            Self::Binary(left, operator, right) => {
+                let left = left.evaluate(variable)?;
+                let right = right.evaluate(variable)?;
+                match operator {
+                    Operator::Add => left + right,
+                    Operator::Subtract => left - right,
+                    Operator::Multiply => left * right,
+                    Operator::Divide => left / right,
+                    Operator::Less => bool_to_f64(left < right),
+                    Operator::LessOrEqual => bool_to_f64(left <= right),
+                    Operator::Greater => bool_to_f64(left > right),
+                    Operator::GreaterOrEqual => bool_to_f64(left >= right),
+                    Operator::Equal => bool_to_f64(left == right),
+                    Operator::NotEqual => bool_to_f64(left != right),
+                    Operator::And => bool_to_f64(left != 0. && right != 0.),
+                    Operator::Or => bool_to_f64(left != 0. || right != 0.),
+                }
+            }
+            Self::Call(function, arguments) => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| argument.evaluate(variable))
+                    .collect::<Option<Vec<_>>>()?;
+                match function {
+                    Function::AllOf => bool_to_f64(arguments.iter().all(|value| *value != 0.)),
+                    Function::AnyOf => bool_to_f64(arguments.iter().any(|value| *value != 0.)),
+                }
+            }
+        };
+        Some(value)
+    }
+
+    /// Variables used by the expression
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Self::Number(_) => Vec::new(),
+            Self::Variable(name) => vec![name.as_str()],
+            Self::Negate(expression) | Self::Not(expression) => expression.variables(),
+            Self::Binary(left, _, right) => {
+                let mut variables = left.variables();
+                variables.extend(right.variables());
+                variables
+            }
+            Self::Call(_, arguments) => arguments
+                .iter()
+                .flat_map(|argument| argument.variables())
+                .collect(),
+        }
+    }
+}
+
+fn bool_to_f64(value: bool) -> f64 {
+    if value {
+        1.
+    } else {
+        0.
+    }
+}
+
+/// Identifiers are case insensitive and alarm names can be used as well: `HealthCheckFailed` is `health_check_failed`
+fn snake_case(identifier: &str) -> String {
+    let mut snake = String::with_capacity(identifier.len() + 4);
+    let mut previous = None::<char>;
+    for char in identifier.chars() {
+        let word_end = previous
+            .is_some_and(|previous| previous.is_ascii_lowercase() || previous.is_ascii_digit());
+        if char.is_ascii_uppercase() && word_end {
+            snake.push('_');
+        }
+        snake.push(char.to_ascii_lowercase());
+        previous = Some(char);
+    }
+    snake
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((index, char)) = chars.next() {
+        let token = match char {
+            _ if char.is_whitespace() => continue,
+            ',' => Token::Comma,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '+' => Token::Operator(Operator::Add),
+            '-' => Token::Operator(Operator::Subtract),
+            '*' => Token::Operator(Operator::Multiply),
+            '/' => Token::Operator(Operator::Divide),
+            '<' | '>' | '=' | '!' | '&' | '|' => {
+                let next = chars.peek().map(|(_, next)| *next);
+                let (token, double) = match (char, next) {
+                    ('<', Some('=')) => (Token::Operator(Operator::LessOrEqual), true),
+                    ('<', _) => (Token::Operator(Operator::Less), false),
+                    ('>', Some('=')) => (Token::Operator(Operator::GreaterOrEqual), true),
+                    ('>', _) => (Token::Operator(Operator::Greater), false),
+                    ('=', Some('=')) => (Token::Operator(Operator::Equal), true),
+                    ('!', Some('=')) => (Token::Operator(Operator::NotEqual), true),
+                    ('!', _) => (Token::Bang, false),
+                    ('&', Some('&')) => (Token::Operator(Operator::And), true),
+                    ('|', Some('|')) => (Token::Operator(Operator::Or), true),
+                    _ => {
+                        return Err(Error::InvalidExpression(
+                            source.to_owned(),
+                            format!("unexpected {char} at {index}"),
+                        ))
+                    }
+                };
+                if double {
+                    let _ = chars.next();
+                }
+                token
+            }
+            _ if char.is_ascii_digit() || char == '.' => {
+                let mut end = index + char.len_utf8();
+                while let Some((next_index, next)) = chars.peek() {
+                    if next.is_ascii_digit() || *next == '.' {
+                        end = next_index + next.len_utf8();
+                        let _ = chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let number = source[index..end].parse::<f64>().map_err(|err| {
+                    Error::InvalidExpression(source.to_owned(), format!("{err} at {index}"))
+                })?;
+                Token::Number(number)
+            }
+            _ if char.is_ascii_alphabetic() || char == '_' => {
+                let mut end = index + char.len_utf8();
+                while let Some((next_index, next)) = chars.peek() {
+                    if next.is_ascii_alphanumeric() || *next == '_' {
+                        end = next_index + next.len_utf8();
+                        let _ = chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                // Logical operators can also be written as words: `A AND NOT B`
+                match snake_case(&source[index..end]).as_str() {
+                    "and" => Token::Operator(Operator::And),
+                    "or" => Token::Operator(Operator::Or),
+                    "not" => Token::Bang,
+                    identifier => Token::Identifier(identifier.to_owned()),
+                }
+            }
+            _ => {
+                return Err(Error::InvalidExpression(
+                    source.to_owned(),
+                    format!("unexpected {char} at {index}"),
+                ))
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Deepest nesting of parentheses, calls, negations and `!` accepted, bounds the recursion of the parser
+const MAX_DEPTH: usize = 64;
+
+/// Recursive descent parser, each method parses one precedence level
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: &str) -> Error {
+        Error::InvalidExpression(self.source.to_owned(), reason.to_owned())
+    }
+
+    /// Parses with `parse` one level deeper, fails past `MAX_DEPTH` instead of overflowing the stack
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Expression>) -> Result<Expression> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error(&format!("nested deeper than {MAX_DEPTH} levels")));
+        }
+        self.depth += 1;
+        let expression = parse(self);
+        self.depth -= 1;
+        expression
+    }
+
+    fn next_operator(&mut self, operators: &[Operator]) -> Option<Operator> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                self.position += 1;
+                Some(*operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn binary(
+        &mut self,
+        operators: &[Operator],
+        operand: fn(&mut Self) -> Result<Expression>,
+    ) -> Result<Expression> {
+        let mut left = operand(self)?;
+        while let Some(operator) = self.next_operator(operators) {
+            let right = operand(self)?;
+            left = Expression::Binary(Box::new(left), operator, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Expression> {
+        self.binary(&[Operator::Or], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expression> {
+        self.binary(&[Operator::And], Self::not)
+    }
+
+    fn not(&mut self) -> Result<Expression> {
+        match self.tokens.get(self.position) {
+            Some(Token::Bang) => {
+                self.position += 1;
+                Ok(Expression::Not(Box::new(self.nested(Self::not)?)))
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expression> {
+        let left = self.sum()?;
+        match self.next_operator(&[
+            Operator::Less,
+            Operator::LessOrEqual,
+            Operator::Greater,
+            Operator::GreaterOrEqual,
+            Operator::Equal,
+            Operator::NotEqual,
+        ]) {
+            Some(operator) => Ok(Expression::Binary(
+                Box::new(left),
+                operator,
+                Box::new(self.sum()?),
+            )),
+            None => Ok(left),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expression> {
+        self.binary(&[Operator::Add, Operator::Subtract], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expression> {
+        self.binary(&[Operator::Multiply, Operator::Divide], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expression> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(Operator::Subtract)) => {
+                self.position += 1;
+                Ok(Expression::Negate(Box::new(self.nested(Self::unary)?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| self.error("unexpected end of expression"))?;
+        self.position += 1;
+
+        match token {
+            Token::Number(number) => Ok(Expression::Number(number)),
+            Token::Identifier(name)
+                if self.tokens.get(self.position) == Some(&Token::OpenParen) =>
+            {
+                self.position += 1;
+                let function = match name.as_str() {
+                    "all_of" => Function::AllOf,
+                    "any_of" => Function::AnyOf,
+                    _ => return Err(self.error(&format!("unknown function {name}"))),
+                };
+
+                let mut arguments = vec![self.nested(Self::or)?];
+                loop {
+                    match self.tokens.get(self.position) {
+                        Some(Token::Comma) => {
+                            self.position += 1;
+                            arguments.push(self.nested(Self::or)?);
+                        }
+                        Some(Token::CloseParen) => {
+                            self.position += 1;
+                            break;
+                        }
+                        _ => return Err(self.error("missing closing parenthesis")),
+                    }
+                }
+                Ok(Expression::Call(function, arguments))
+            }
+            Token::Identifier(name) => Ok(Expression::Variable(name)),
+            Token::OpenParen => {
+                let expression = self.nested(Self::or)?;
+                match self.tokens.get(self.position) {
+                    Some(Token::CloseParen) => {
+                        self.position += 1;
+                        Ok(expression)
+                    }
+                    _ => Err(self.error("missing closing parenthesis")),
+                }
+            }
+            token => Err(self.error(&format!("unexpected {token:?}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Expression;
+    use crate::Error;
+
+    const VARIABLES: [&str; 3] = ["cpu", "memory_gb", "memory_limit_gb"];
+
+    fn evaluate(source: &str) -> Option<f64> {
+        let expression = Expression::parse(source, &VARIABLES).expect("unable to parse expression");
+        expression.evaluate(&|name| match name {
+            "cpu" => Some(3.),
+            "memory_gb" => Some(4.5),
+            "memory_limit_gb" => Some(5.),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn expression() {
+        assert_eq!(evaluate("memory_gb / memory_limit_gb * 100 > 85"), Some(1.));
+        assert_eq!(evaluate("memory_gb / memory_limit_gb * 100 > 95"), Some(0.));
+        assert_eq!(evaluate("cpu > 2 && memory_gb > 4"), Some(1.));
+        assert_eq!(evaluate("cpu > 4 || !(memory_gb <= 4)"), Some(1.));
+        assert_eq!(evaluate("-cpu + 2 * (1 + 1)"), Some(1.));
+        assert_eq!(evaluate("CPU >= 3"), Some(1.));
+
+        assert!(Expression::parse("cpu >", &VARIABLES).is_err());
+        assert!(Expression::parse("(cpu > 1", &VARIABLES).is_err());
+        assert!(Expression::parse("cpu > 1 1", &VARIABLES).is_err());
+        assert!(Expression::parse("disk_gb > 1", &VARIABLES).is_err());
+        assert!(Expression::parse("cpu & 1", &VARIABLES).is_err());
+
+        assert_eq!(evaluate("cpu > 2 AND NOT memory_gb > 5"), Some(1.));
+        assert_eq!(evaluate("any_of(cpu > 4, memory_gb > 4)"), Some(1.));
+        assert_eq!(evaluate("all_of(cpu > 4, memory_gb > 4)"), Some(0.));
+        assert!(Expression::parse("any_of(cpu > 4,", &VARIABLES).is_err());
+        assert!(Expression::parse("max(cpu, 1)", &VARIABLES).is_err());
+
+        assert_eq!(evaluate("MemoryGb > 4 AND MEMORY_LIMIT_GB > 4"), Some(1.));
+        assert_eq!(evaluate("any_of(Cpu > 4, MemoryGb > 5)"), Some(0.));
+
+        assert_eq!(
+            evaluate(&format!("{}cpu{}", "(".repeat(64), ")".repeat(64))),
+            Some(3.)
+        );
+        for nested in [
+            format!("{}cpu{}", "(".repeat(20_000), ")".repeat(20_000)),
+            format!("{}cpu", "!".repeat(20_000)),
+            format!("{}cpu", "-".repeat(20_000)),
+            format!("{}cpu{}", "any_of(".repeat(20_000), ")".repeat(20_000)),
+        ] {
+            assert!(matches!(
+                Expression::parse(&nested, &VARIABLES),
+                Err(Error::InvalidExpression(..))
+            ));
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// How the samples collected during a period are combined into a single data point
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Statistic {
+    Average,
+    Maximum,
+    Minimum,
+    /// Percentile between 0 (exclusive) and 100 (inclusive), like `p99`
+    Percentile(f64),
+    Sum,
+}
+
+impl Statistic {
+    /// Maximum, minimum and percentiles pick one of the samples instead of aggregating the period
+    pub fn is_point(self) -> bool {
+        matches!(self, Self::Maximum | Self::Minimum | Self::Percentile(_))
+    }
+
+    /// Returns `None` if there are no samples
+    pub fn compute(self, samples: &[f64]) -> Option<f64> {
+        if samples.is_empty() {
+            return None;
+        }
+
+        let value = match self {
+            Self::Average => samples.iter().sum::<f64>() / samples.len() as f64,
+            Self::Maximum => samples.iter().copied().fold(f64::MIN, f64::max),
+            Self::Minimum => samples.iter().copied().fold(f64::MAX, f64::min),
+            Self::Sum => samples.iter().sum(),
+            Self::Percentile(percentile) => {
+                let mut sorted = samples.to_vec();
+                sorted.sort_by(f64::total_cmp);
+
+                // Nearest-rank method
+                let rank = (percentile / 100. * sorted.len() as f64).ceil() as usize;
+                sorted[rank.clamp(1, sorted.len()) - 1]
+            }
+        };
+        Some(value)
+    }
+}
+
+impl fmt::Display for Statistic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Average => write!(f, "average"),
+            Self::Maximum => write!(f, "maximum"),
+            Self::Minimum => write!(f, "minimum"),
+            Self::Percentile(percentile) => write!(f, "p{percentile}"),
+            Self::Sum => write!(f, "sum"),
+        }
+    }
+}
+
+impl FromStr for Statistic {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "average" | "avg" | "mean" => Ok(Self::Average),
+            "maximum" | "max" => Ok(Self::Maximum),
+            "minimum" | "min" => Ok(Self::Minimum),
+            "sum" => Ok(Self::Sum),
+            other => match other.strip_prefix('p').map(str::parse::<f64>) {
+                Some(Ok(percentile)) if percentile > 0. && percentile <= 100. => {
+                    Ok(Self::Percentile(percentile))
+                }
+                _ => Err(Error::InvalidStatistic(value.to_owned())),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for Statistic {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Statistic> for String {
+    fn from(statistic: Statistic) -> Self {
+        statistic.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Statistic;
+
+    #[test]
+    fn compute() {
+        let samples = [4., 1., 3., 2., 10.];
+        assert_eq!(Statistic::Average.compute(&samples), Some(4.));
+        assert_eq!(Statistic::Maximum.compute(&samples), Some(10.));
+        assert_eq!(Statistic::Minimum.compute(&samples), Some(1.));
+        assert_eq!(Statistic::Sum.compute(&samples), Some(20.));
+        assert_eq!(Statistic::Percentile(50.).compute(&samples), Some(3.));
+        assert_eq!(Statistic::Percentile(90.).compute(&samples), Some(10.));
+        assert_eq!(Statistic::Percentile(100.).compute(&samples), Some(10.));
+        assert_eq!(Statistic::Average.compute(&[]), None);
+
+        assert_eq!(
+            "P99".parse::<Statistic>().ok(),
+            Some(Statistic::Percentile(99.))
+        );
+        assert_eq!("max".parse::<Statistic>().ok(), Some(Statistic::Maximum));
+        assert!("p0".parse::<Statistic>().is_err());
+        assert!("p101".parse::<Statistic>().is_err());
+        assert!("median".parse::<Statistic>().is_err());
+    }
+}
@@ -0,0 +1,349 @@
+use crate::{
+    action::MIN_ACTION_MAX_EXECUTIONS, Action, ActionKind, Alarm, AlarmKey, Error, Expression,
+    Result, Statistic, Usage,
+};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Unset fields get the defaults of the environment variables
+#[derive(Getters, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AlarmConfig {
+    pub value: String,
+    /// Seconds of each period, always a multiple of `MIN_PERIOD_SECS`
+    #[copy]
+    pub period_secs: u32,
+    #[copy]
+    pub data_points: u16,
+    #[copy]
+    pub data_points_to_alarm: u16,
+    #[copy]
+    pub per_replica: bool,
+    /// Number of log lines that can match the pattern during the period without breaching
+    #[serde(default)]
+    #[copy]
+    pub max_lines: u32,
+    #[copy]
+    pub statistic: Statistic,
+    /// Composite alarms in the ALARM state withhold the notifications of the alarms they combine
+    #[serde(default)]
+    #[copy]
+    pub suppress: bool,
+    /// Minutes of history used to fit the trend of growth and forecast alarms
+    #[serde(default = "default_window_minutes")]
+    #[copy]
+    pub window_minutes: u32,
+    /// Hours ahead that forecast alarms project the trend to
+    #[serde(default = "default_horizon_hours")]
+    #[copy]
+    pub horizon_hours: u32,
+    /// Threshold the measurement must cross back to recover, same as the value if unset
+    #[serde(default)]
+    #[copy]
+    pub recovery: Option<f64>,
+    /// Consecutive recovered data points required to resolve, if unset it resolves when the breaching ones are below `data_points_to_alarm`
+    #[serde(default)]
+    #[copy]
+    pub recovery_data_points: Option<u16>,
+    /// Holds the alarm open once it changed state more than this within the last `flap_data_points`, `0` disables it
+    #[serde(default)]
+    #[copy]
+    pub flap_limit: u16,
+    #[serde(default = "default_flap_data_points")]
+    #[copy]
+    pub flap_data_points: u16,
+    /// Runs when the alarm enters the ALARM state
+    #[serde(default)]
+    #[copy]
+    pub action: Option<Action>,
+    /// Variable monitored by an anomaly alarm, the name of the alarm if unset
+    #[serde(default)]
+    pub variable: Option<String>,
+}
+
+pub const DEFAULT_PERIOD_SECS: u32 = 60;
+pub const MIN_PERIOD_SECS: u32 = 10;
+
+pub const DEFAULT_DATA_POINTS: u16 = 5;
+pub const MIN_DATA_POINTS: u16 = 1;
+
+pub const DEFAULT_DATA_POINTS_TO_ALARM: u16 = 3;
+pub const MIN_DATA_POINTS_TO_ALARM: u16 = 1;
+
+pub const DEFAULT_PER_REPLICA: bool = false;
+
+pub const DEFAULT_MAX_LINES: u32 = 0;
+
+pub const DEFAULT_STATISTIC: Statistic = Statistic::Average;
+
+pub const DEFAULT_SUPPRESS: bool = false;
+
+pub const DEFAULT_WINDOW_MINUTES: u32 = 60;
+pub const MIN_WINDOW_MINUTES: u32 = 2;
+
+pub const DEFAULT_HORIZON_HOURS: u32 = 24;
+
+pub const DEFAULT_FLAP_LIMIT: u16 = 0;
+
+pub const DEFAULT_FLAP_DATA_POINTS: u16 = 10;
+pub const MIN_FLAP_DATA_POINTS: u16 = 1;
+
+fn default_flap_data_points() -> u16 {
+    DEFAULT_FLAP_DATA_POINTS
+}
+
+fn default_window_minutes() -> u32 {
+    DEFAULT_WINDOW_MINUTES
+}
+
+fn default_horizon_hours() -> u32 {
+    DEFAULT_HORIZON_HOURS
+}
+
+impl Default for AlarmConfig {
+    fn default() -> Self {
+        Self {
+            value: String::new(),
+            period_secs: DEFAULT_PERIOD_SECS,
+            data_points: DEFAULT_DATA_POINTS,
+            data_points_to_alarm: DEFAULT_DATA_POINTS_TO_ALARM,
+            per_replica: DEFAULT_PER_REPLICA,
+            max_lines: DEFAULT_MAX_LINES,
+            statistic: DEFAULT_STATISTIC,
+            suppress: DEFAULT_SUPPRESS,
+            window_minutes: DEFAULT_WINDOW_MINUTES,
+            horizon_hours: DEFAULT_HORIZON_HOURS,
+            recovery: None,
+            recovery_data_points: None,
+            flap_limit: DEFAULT_FLAP_LIMIT,
+            flap_data_points: DEFAULT_FLAP_DATA_POINTS,
+            action: None,
+            variable: None,
+        }
+    }
+}
+
+impl AlarmConfig {
+    /// Variable monitored by an anomaly alarm, so more than one alarm can monitor the same variable
+    pub fn anomaly_variable(&self, key: &AlarmKey) -> String {
+        self.variable
+            .as_deref()
+            .or(key.name().as_deref())
+            .unwrap_or_default()
+            .to_lowercase()
+    }
+
+    /// Validates the value that configures the alarm, returns `false` if it disables the alarm
+    pub fn validate(key: &AlarmKey, value: &str) -> Result<bool> {
+        // Short term solution to allow both alarm types with the same env var machinery
+        // The correct solution is having a AlarmWithPaylaod type that adds a value tuple to each variant of Alarm
+        match key.alarm() {
+            // Validated after all alarms are configured
+            Alarm::Composite | Alarm::HealthCheckFailed => {}
+            Alarm::DeploymentCrashed | Alarm::DeploymentFailed | Alarm::DeploymentRemoved => {
+                let enabled = value
+                    .parse::<bool>()
+                    .map_err(|err| Error::ParseBoolWithMetadata(err, key.to_string()))?;
+                if !enabled {
+                    return Ok(false);
+                }
+            }
+            Alarm::Anomaly => {
+                if let Err(err) = value.parse::<f64>() {
+                    return Err(Error::ParseFloatWithMetadata(err, key.to_string()));
+                }
+            }
+            Alarm::Expression => {
+                let _ = Expression::parse(value, &Usage::VARIABLES)?;
+            }
+            Alarm::LogMatched => {
+                if let Err(err) = regex::Regex::new(value) {
+                    return Err(Error::RegexWithMetadata(err, key.to_string()));
+                }
+            }
+            _ => {
+                if let Err(err) = value.parse::<f64>() {
+                    return Err(Error::ParseFloatWithMetadata(err, key.to_string()));
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Validates the whole configuration with the rules applied to the environment variables, returns `false` if it
+    /// disables the alarm. Settings below their minimum are rejected instead of raised to it
+    pub fn check(&self, key: &AlarmKey) -> Result<bool> {
+        let valid_name = |name: &str| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+        };
+        match key.name() {
+            Some(name) if key.alarm().is_named() && valid_name(name) => {}
+            None if !key.alarm().is_named() => {}
+            _ => return Err(Error::InvalidAlarmName(key.to_string())),
+        }
+
+        if !Self::validate(key, &self.value)? {
+            return Ok(false);
+        }
+
+        let invalid = |setting: &str, reason: String| {
+            Err(Error::InvalidSetting(format!("{key}_{setting}"), reason))
+        };
+        if self.period_secs < MIN_PERIOD_SECS || !self.period_secs.is_multiple_of(MIN_PERIOD_SECS) {
+            return invalid(
+                "PERIOD_SECS",
+                format!("must be a multiple of {MIN_PERIOD_SECS} and can't be below it"),
+            );
+        }
+        if self.data_points < MIN_DATA_POINTS {
+            return invalid("DATA_POINTS", format!("can't be below {MIN_DATA_POINTS}"));
+        }
+        if self.data_points_to_alarm < MIN_DATA_POINTS_TO_ALARM {
+            return invalid(
+                "DATA_POINTS_TO_ALARM",
+                format!("can't be below {MIN_DATA_POINTS_TO_ALARM}"),
+            );
+        }
+        if self.window_minutes < MIN_WINDOW_MINUTES {
+            return invalid(
+                "WINDOW_MINUTES",
+                format!("can't be below {MIN_WINDOW_MINUTES}"),
+            );
+        }
+        if self
+            .recovery_data_points
+            .is_some_and(|points| points < MIN_DATA_POINTS)
+        {
+            return invalid(
+                "RECOVERY_DATA_POINTS",
+                format!("can't be below {MIN_DATA_POINTS}"),
+            );
+        }
+        if self.flap_data_points < MIN_FLAP_DATA_POINTS {
+            return invalid(
+                "FLAP_DATA_POINTS",
+                format!("can't be below {MIN_FLAP_DATA_POINTS}"),
+            );
+        }
+
+        if key.alarm() == Alarm::Anomaly
+            && !Usage::VARIABLES.contains(&self.anomaly_variable(key).as_str())
+        {
+            return Err(Error::UnknownVariable(
+                format!("{key}_VARIABLE"),
+                Usage::VARIABLES.join(", "),
+            ));
+        }
+
+        if let Some(action) = self.action {
+            if action.kind == ActionKind::Replicas && action.replicas.is_none() {
+                return invalid("ACTION_REPLICAS", "is required by REPLICAS".to_owned());
+            }
+            if action.max_executions < MIN_ACTION_MAX_EXECUTIONS {
+                return invalid(
+                    "ACTION_MAX_EXECUTIONS",
+                    format!("can't be below {MIN_ACTION_MAX_EXECUTIONS}"),
+                );
+            }
+        }
+        Ok(true)
+    }
+
+    /// Composite alarms can only be validated once all the alarms they combine are known
+    pub fn validate_composites(configs: &HashMap<AlarmKey, AlarmConfig>) -> Result<()> {
+        let variables = configs
+            .keys()
+            .filter(|key| key.alarm() != Alarm::Composite)
+            .map(AlarmKey::variable)
+            .collect::<Vec<_>>();
+        let variables = variables.iter().map(String::as_str).collect::<Vec<_>>();
+        for (key, config) in configs {
+            if key.alarm() == Alarm::Composite {
+                let _ = Expression::parse(config.value(), &variables)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AlarmConfig;
+    use crate::{Action, ActionKind, Alarm, AlarmKey, Error};
+    use std::collections::HashMap;
+
+    #[test]
+    fn check() {
+        let cpu = AlarmKey::from(Alarm::CpuUpperLimitVcpus);
+        let config = |value: &str| AlarmConfig {
+            value: value.to_owned(),
+            ..AlarmConfig::default()
+        };
+        assert!(config("1.5").check(&cpu).expect("valid config"));
+        assert!(config("a").check(&cpu).is_err());
+
+        // Named alarms need a name that fits an environment variable, the others can't have one
+        let named = AlarmKey::named(Alarm::LogMatched, "ERRORS".to_owned());
+        assert!(config("error").check(&named).expect("valid name"));
+        let lowercase = AlarmKey::named(Alarm::LogMatched, "errors".to_owned());
+        assert!(matches!(
+            config("error").check(&lowercase),
+            Err(Error::InvalidAlarmName(_))
+        ));
+        assert!(config("error")
+            .check(&AlarmKey::from(Alarm::LogMatched))
+            .is_err());
+        assert!(config("1")
+            .check(&AlarmKey::named(Alarm::CpuUpperLimitVcpus, "A".to_owned()))
+            .is_err());
+
+        // Rejected instead of raised to the minimum
+        let period = AlarmConfig {
+            period_secs: 15,
+            ..config("1")
+        };
+        assert!(matches!(
+            period.check(&cpu),
+            Err(Error::InvalidSetting(setting, _)) if setting == "CPU_UPPER_LIMIT_VCPUS_PERIOD_SECS"
+        ));
+        let action = AlarmConfig {
+            action: Some(Action::new(ActionKind::Replicas)),
+            ..config("1")
+        };
+        assert!(action.check(&cpu).is_err());
+
+        // Anomaly alarms are named after their variable unless it's set
+        let spike = AlarmKey::named(Alarm::Anomaly, "SPIKE".to_owned());
+        assert!(matches!(
+            config("3").check(&spike),
+            Err(Error::UnknownVariable(..))
+        ));
+        let ingress = AlarmConfig {
+            variable: Some("ingress_gb".to_owned()),
+            ..config("3")
+        };
+        assert!(ingress.check(&spike).expect("valid variable"));
+        let ingress_gb = AlarmKey::named(Alarm::Anomaly, "INGRESS_GB".to_owned());
+        assert!(config("3").check(&ingress_gb).expect("valid variable"));
+
+        // Disabled
+        let crashed = AlarmKey::from(Alarm::DeploymentCrashed);
+        assert!(!config("false").check(&crashed).expect("valid config"));
+
+        let composite = AlarmKey::named(Alarm::Composite, "BOTH".to_owned());
+        let mut configs = HashMap::from([
+            (cpu.clone(), config("1")),
+            (
+                composite,
+                config("cpu_upper_limit_vcpus && memory_upper_limit_gb"),
+            ),
+        ]);
+        assert!(AlarmConfig::validate_composites(&configs).is_err());
+        configs.insert(Alarm::MemoryUpperLimitGb.into(), config("1"));
+        AlarmConfig::validate_composites(&configs).expect("valid composites");
+    }
+}
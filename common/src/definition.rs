@@ -0,0 +1,43 @@
+use crate::{AlarmConfig, AlarmKey};
+use chrono::{DateTime, Utc};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+
+/// Alarm of a service managed through the backend, configured the same way as its environment variables
+#[derive(Getters, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AlarmDefinition {
+    #[copy]
+    id: i64,
+    project_id: String,
+    service_id: String,
+    #[serde(flatten)]
+    key: AlarmKey,
+    config: AlarmConfig,
+    #[copy]
+    created_at: DateTime<Utc>,
+    #[copy]
+    updated_at: DateTime<Utc>,
+}
+
+impl AlarmDefinition {
+    pub fn new(
+        id: i64,
+        project_id: String,
+        service_id: String,
+        key: AlarmKey,
+        config: AlarmConfig,
+        created_at: DateTime<Utc>,
+        updated_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id,
+            project_id,
+            service_id,
+            key,
+            config,
+            created_at,
+            updated_at,
+        }
+    }
+}
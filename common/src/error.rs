@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use std::{
+    num::{ParseFloatError, ParseIntError},
+    str::ParseBoolError,
+};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[remain::sorted]
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("date out of range: {0} - {1}")]
+    DateOutOfRange(DateTime<Utc>, i64),
+    #[error("unable to build http client: {0}")]
+    HttpClient(reqwest::Error),
+    #[error("invalid action {0}, expected one of: {1}")]
+    InvalidAction(String, String),
+    #[error("invalid alarm name: {0}")]
+    InvalidAlarmName(String),
+    #[error("invalid expression {0}: {1}")]
+    InvalidExpression(String, String),
+    #[error("invalid {0}: {1}")]
+    InvalidSetting(String, String),
+    #[error("invalid statistic: {0}")]
+    InvalidStatistic(String),
+    #[error("invalid time delta: secs = {0}, nano = {1}")]
+    InvalidTimeDelta(i64, i64),
+    #[error(transparent)]
+    Json(serde_json::Error),
+    #[error("json error: {0} with payload {1:#?}")]
+    JsonWithMetadata(serde_json::Error, serde_json::Value),
+    #[error("parse bool error for {1}: {0}")]
+    ParseBoolWithMetadata(ParseBoolError, String),
+    #[error("parse date error for {1}: {0}")]
+    ParseDateWithMetadata(chrono::ParseError, String),
+    #[error("parse float error for {1}: {0}")]
+    ParseFloatWithMetadata(ParseFloatError, String),
+    #[error("parse int error for {1}: {0}")]
+    ParseIntWithMetadata(ParseIntError, String),
+    #[error("railway responded with: {0:?}")]
+    Railway(Vec<String>),
+    #[error("railway reqwest body error for {1}: {0} ({2:#?})")]
+    RailwayBody(reqwest::Error, String, serde_json::Value),
+    #[error("railway data missing: {0}")]
+    RailwayDataMissing(&'static str),
+    #[error("railway reqwest failure for {1}: {0} ({2:#?})")]
+    RailwayFailure(reqwest::Error, String, serde_json::Value),
+    #[error("railway rejected the mutation {0}")]
+    RailwayMutationRejected(&'static str),
+    #[error("railway quota exhausted for {0}s more, request not sent")]
+    RailwayQuotaExhausted(u64),
+    #[error("railway request failed with status {0}: {1}")]
+    RailwayStatusFailure(u16, String),
+    #[error("invalid regex for {1}: {0}")]
+    RegexWithMetadata(regex::Error, String),
+    #[error("unknown variable for {0}, expected one of: {1}")]
+    UnknownVariable(String, String),
+}
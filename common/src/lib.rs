@@ -0,0 +1,23 @@
+mod alarm;
+pub mod config;
+mod definition;
+mod error;
+mod railway;
+#[cfg(any(test, feature = "testing"))]
+mod testing;
+
+pub use alarm::{
+    action, Action, ActionKind, Alarm, AlarmKey, AlarmState, Expression, Function, Operator,
+    Statistic,
+};
+pub use config::AlarmConfig;
+pub use definition::AlarmDefinition;
+pub use error::{Error, Result};
+pub use railway::{
+    deployment::{Deployment, DeploymentStatus, Log},
+    project::{Project, ProjectService},
+    service::{Limits, Service, Usage},
+    HttpTransport, RailwayClient, RailwayError, RailwayResponse, Transport,
+};
+#[cfg(any(test, feature = "testing"))]
+pub use testing::FakeRailway;
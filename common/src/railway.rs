@@ -0,0 +1,384 @@
+use crate::{config::DEFAULT_PERIOD_SECS, Error, Result};
+use async_trait::async_trait;
+use graphql_client::GraphQLQuery;
+use rate_limit::RateLimiter;
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::{debug, warn};
+
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+const TIMEOUT_SECS: u64 = 30;
+
+const DEFAULT_REQUESTS_PER_SECOND: f64 = 10.;
+/// Slower rates would hold a request for minutes
+const MIN_REQUESTS_PER_SECOND: f64 = 0.01;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Backoff of the first retry, doubled on each of the following ones
+const BASE_BACKOFF_MILLIS: u64 = 500;
+const MAX_BACKOFF_SECS: u64 = 30;
+/// Railway may ask to wait until its quota of the hour resets, longer waits are cut short
+const MAX_QUOTA_RESET_SECS: u64 = 3600;
+/// Requests that would wait longer than a period for Railway's quota fail instead, so the data point is missed rather
+/// than evaluated late
+const MAX_RATE_LIMIT_WAIT_SECS: u64 = DEFAULT_PERIOD_SECS as u64;
+
+/// Implements [`Paginated`] for an operation, given the connection in its response
+macro_rules! paginated {
+    ($operation:ident, $node:ty, |$data:ident| $connection:expr) => {
+        impl $crate::railway::Paginated for $operation {
+            type Node = $node;
+
+            fn after(variables: &mut Self::Variables, cursor: Option<String>) {
+                variables.after = cursor;
+            }
+
+            fn page($data: Self::ResponseData) -> $crate::railway::Page<Self::Node> {
+                let connection = $connection;
+                $crate::railway::Page {
+                    nodes: connection.edges.into_iter().map(|edge| edge.node).collect(),
+                    end_cursor: connection.page_info.end_cursor,
+                    has_next_page: connection.page_info.has_next_page,
+                }
+            }
+        }
+    };
+}
+
+pub mod deployment;
+mod operation;
+pub mod project;
+mod rate_limit;
+pub mod service;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RailwayError {
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(bound(deserialize = "T: Deserialize<'de>"))]
+pub struct RailwayResponse<T> {
+    #[serde(default)]
+    pub data: Option<T>,
+    #[serde(default)]
+    pub errors: Vec<RailwayError>,
+}
+
+/// Nodes of a page of a connection, and the cursor the next page starts after
+pub struct Page<N> {
+    pub nodes: Vec<N>,
+    pub end_cursor: Option<String>,
+    pub has_next_page: bool,
+}
+
+/// Operation selecting a connection, its `$after` variable requests the pages after the first one
+pub trait Paginated: GraphQLQuery {
+    type Node;
+
+    fn after(variables: &mut Self::Variables, cursor: Option<String>);
+
+    fn page(data: Self::ResponseData) -> Page<Self::Node>;
+}
+
+/// Sends the GraphQL requests to Railway, replaceable to test without reaching it
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Returns the JSON body of a successful response, with the data or the errors of the query
+    async fn send(&self, token: &str, json: &serde_json::Value) -> Result<serde_json::Value>;
+}
+
+/// Sends the requests over a pooled connection to `RAILWAY_API_URL`, or to Railway's public API if unset.
+/// Requests are rate limited client-side and retried with backoff when Railway throttles them or fails
+pub struct HttpTransport {
+    client: reqwest::Client,
+    url: String,
+    limiter: RateLimiter,
+    max_retries: u32,
+    /// Responses throttled by Railway since the transport was created
+    throttled: AtomicU64,
+}
+
+impl HttpTransport {
+    pub fn new() -> Result<Self> {
+        let url = std::env::var("RAILWAY_API_URL")
+            .unwrap_or_else(|_| "https://backboard.railway.app".to_owned());
+        let requests_per_sec = std::env::var("RAILWAY_API_REQUESTS_PER_SECOND")
+            .ok()
+            .map(|value| value.parse::<f64>())
+            .transpose()
+            .map_err(|err| {
+                Error::ParseFloatWithMetadata(err, "RAILWAY_API_REQUESTS_PER_SECOND".to_owned())
+            })?
+            .unwrap_or(DEFAULT_REQUESTS_PER_SECOND);
+        if !requests_per_sec.is_finite() || requests_per_sec < MIN_REQUESTS_PER_SECOND {
+            return Err(Error::InvalidSetting(
+                "RAILWAY_API_REQUESTS_PER_SECOND".to_owned(),
+                format!("{requests_per_sec}, must be a finite number of at least {MIN_REQUESTS_PER_SECOND}"),
+            ));
+        }
+        let max_retries = std::env::var("RAILWAY_API_MAX_RETRIES")
+            .ok()
+            .map(|value| value.parse::<u32>())
+            .transpose()
+            .map_err(|err| Error::ParseIntWithMetadata(err, "RAILWAY_API_MAX_RETRIES".to_owned()))?
+            .unwrap_or(DEFAULT_MAX_RETRIES);
+
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS))
+            .timeout(Duration::from_secs(TIMEOUT_SECS))
+            .build()
+            .map_err(Error::HttpClient)?;
+        Ok(Self {
+            client,
+            url: format!("{url}/graphql/v2"),
+            limiter: RateLimiter::new(
+                requests_per_sec,
+                Duration::from_secs(MAX_RATE_LIMIT_WAIT_SECS),
+            ),
+            max_retries,
+            throttled: AtomicU64::new(0),
+        })
+    }
+
+    /// Responses throttled by Railway so far, each one is also logged
+    pub fn throttled(&self) -> u64 {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    /// Holds the following requests if Railway reports the quota is exhausted
+    fn observe(&self, headers: &HeaderMap) {
+        let remaining =
+            header(headers, "x-ratelimit-remaining").and_then(|value| value.parse::<u64>().ok());
+        if remaining == Some(0) {
+            if let Some(reset) = header(headers, "x-ratelimit-reset").and_then(reset_after) {
+                warn!(
+                    "Railway quota exhausted, holding requests for {}s",
+                    reset.as_secs()
+                );
+                self.limiter.block_for(reset);
+            }
+        }
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Railway sends either the seconds to wait, or the date the quota resets at as a timestamp or RFC 3339
+fn reset_after(value: &str) -> Option<Duration> {
+    let now = chrono::Utc::now();
+    let secs = match value.trim().parse::<i64>() {
+        // Seconds since the epoch, not seconds to wait
+        Ok(timestamp) if timestamp > 1_000_000_000 => timestamp - now.timestamp(),
+        Ok(secs) => secs,
+        Err(_) => {
+            let date = chrono::DateTime::parse_from_rfc3339(value.trim()).ok()?;
+            (date.with_timezone(&chrono::Utc) - now).num_seconds()
+        }
+    };
+    let secs = u64::try_from(secs).unwrap_or(0).min(MAX_QUOTA_RESET_SECS);
+    Some(Duration::from_secs(secs))
+}
+
+fn is_mutation(json: &serde_json::Value) -> bool {
+    json["query"]
+        .as_str()
+        .is_some_and(|query| query.trim_start().starts_with("mutation"))
+}
+
+fn backoff(attempt: u32) -> Duration {
+    let millis = BASE_BACKOFF_MILLIS.saturating_mul(2u64.saturating_pow(attempt));
+    Duration::from_millis(millis).min(Duration::from_secs(MAX_BACKOFF_SECS))
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+    async fn send(&self, token: &str, json: &serde_json::Value) -> Result<serde_json::Value> {
+        let url = &self.url;
+        let mut attempt = 0;
+        loop {
+            self.limiter
+                .acquire()
+                .await
+                .map_err(|wait| Error::RailwayQuotaExhausted(wait.as_secs()))?;
+            let response = self
+                .client
+                .post(url)
+                .header("Authorization", format!("Bearer {token}"))
+                .json(json)
+                .fetch_mode_no_cors()
+                .send()
+                .await
+                .map_err(|err| Error::RailwayFailure(err, url.clone(), json.clone()))?;
+
+            let status = response.status();
+            self.observe(response.headers());
+
+            let throttled = status == StatusCode::TOO_MANY_REQUESTS;
+            let delay = header(response.headers(), "retry-after")
+                .and_then(reset_after)
+                .unwrap_or_else(|| backoff(attempt));
+            let retry = delay <= Duration::from_secs(MAX_RATE_LIMIT_WAIT_SECS);
+            // A mutation that failed on Railway's side may have been applied, retrying could restart the service twice
+            let failed = status.is_server_error() && !is_mutation(json);
+            if (throttled || failed) && attempt < self.max_retries && retry {
+                attempt += 1;
+
+                if throttled {
+                    let count = self.throttled.fetch_add(1, Ordering::Relaxed) + 1;
+                    warn!(
+                        "Throttled by Railway ({count} times so far), retry {attempt} of {} in {}ms",
+                        self.max_retries,
+                        delay.as_millis()
+                    );
+                    self.limiter.block_for(delay);
+                } else {
+                    warn!(
+                        "Railway responded with {status}, retry {attempt} of {} in {}ms",
+                        self.max_retries,
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                continue;
+            }
+
+            if status != 200 {
+                if throttled {
+                    self.throttled.fetch_add(1, Ordering::Relaxed);
+                }
+                return Err(Error::RailwayStatusFailure(
+                    status.as_u16(),
+                    response
+                        .text()
+                        .await
+                        .map_err(|err| Error::RailwayBody(err, url.clone(), json.clone()))?,
+                ));
+            }
+
+            return response
+                .json()
+                .await
+                .map_err(|err| Error::RailwayBody(err, url.clone(), json.clone()));
+        }
+    }
+}
+
+/// Authenticated client of Railway's GraphQL API, cloning it shares the connections
+#[derive(Clone)]
+pub struct RailwayClient {
+    token: String,
+    transport: Arc<dyn Transport>,
+}
+
+impl RailwayClient {
+    pub fn new() -> Result<Self> {
+        Ok(Self::with_transport(Arc::new(HttpTransport::new()?)))
+    }
+
+    pub fn with_transport(transport: Arc<dyn Transport>) -> Self {
+        Self {
+            token: String::new(),
+            transport,
+        }
+    }
+
+    pub fn with_token(&self, token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+            transport: Arc::clone(&self.transport),
+        }
+    }
+
+    /// Executes an operation generated from the schema
+    pub async fn execute<Q: GraphQLQuery>(&self, variables: Q::Variables) -> Result<Q::ResponseData>
+    where
+        Q::ResponseData: std::fmt::Debug,
+    {
+        let body = Q::build_query(variables);
+        let json = serde_json::to_value(&body).map_err(Error::Json)?;
+        self.query(json).await
+    }
+
+    /// Executes the operation for every page of its connection, returning the nodes of all of them
+    pub async fn paginate<Q: Paginated>(&self, mut variables: Q::Variables) -> Result<Vec<Q::Node>>
+    where
+        Q::Variables: Clone,
+        Q::ResponseData: std::fmt::Debug,
+    {
+        let mut nodes = Vec::new();
+        loop {
+            let page = Q::page(self.execute::<Q>(variables.clone()).await?);
+            nodes.extend(page.nodes);
+            match page.end_cursor {
+                Some(cursor) if page.has_next_page => Q::after(&mut variables, Some(cursor)),
+                _ => return Ok(nodes),
+            }
+        }
+    }
+
+    pub async fn query<T: serde::de::DeserializeOwned + std::fmt::Debug>(
+        &self,
+        json: serde_json::Value,
+    ) -> Result<T> {
+        debug!("Executing query: {json:#?}");
+
+        let json = self.transport.send(&self.token, &json).await?;
+        let response = RailwayResponse::<T>::deserialize(&json)
+            .map_err(|err| Error::JsonWithMetadata(err, json))?;
+        debug!("Output: {response:#?}");
+
+        if !response.errors.is_empty() {
+            Err(Error::Railway(
+                response.errors.into_iter().map(|e| e.message).collect(),
+            ))
+        } else if let Some(data) = response.data {
+            Ok(data)
+        } else {
+            Err(Error::RailwayDataMissing("no data returned for: {query}"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{FakeRailway, Project, RailwayClient};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn paginate() {
+        let railway = Arc::new(FakeRailway::new());
+        for (name, has_next_page) in [("first", true), ("second", false)] {
+            railway.respond(
+                "projects",
+                serde_json::json!({
+                    "projects": {
+                        "edges": [{ "node": { "id": name, "name": name } }],
+                        "pageInfo": { "hasNextPage": has_next_page, "endCursor": name },
+                    },
+                }),
+            );
+        }
+
+        let client = RailwayClient::with_transport(railway.clone());
+        let projects = Project::list(&client).await.unwrap();
+        assert_eq!(
+            projects.iter().map(|p| p.id().as_str()).collect::<Vec<_>>(),
+            ["first", "second"]
+        );
+
+        let requests = railway.requests("projects");
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0]["after"], serde_json::Value::Null);
+        assert_eq!(requests[1]["after"], "first");
+    }
+}
@@ -0,0 +1,213 @@
+use super::operation::{self, deployments, executions, logs, redeploy, restart, stop};
+use crate::{Error, RailwayClient, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use strum::Display;
+use tracing::warn;
+
+/// Maximum number of log lines fetched for each period
+const MAX_LOG_LINES: u32 = 5000;
+
+/// Executions started this close to the first one are replicas starting with the deployment, not restarts
+const STARTUP_GRACE_SECS: i64 = 60;
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash, Display)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum DeploymentStatus {
+    Building,
+    Crashed,
+    Deploying,
+    Failed,
+    Initializing,
+    NeedsApproval,
+    Queued,
+    Removed,
+    Removing,
+    Skipped,
+    Sleeping,
+    Success,
+    Waiting,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Deployment {
+    id: String,
+    #[copy]
+    status: DeploymentStatus,
+    #[copy]
+    created_at: DateTime<Utc>,
+    /// Number of times the deployment instances were restarted during the period
+    #[copy]
+    restarts: u32,
+}
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Log {
+    message: String,
+    #[serde(default)]
+    severity: Option<String>,
+    #[copy]
+    timestamp: DateTime<Utc>,
+}
+
+impl Deployment {
+    /// Returns the most recent deployment of the service, if it was ever deployed
+    pub async fn latest(
+        railway: &RailwayClient,
+        project_id: &str,
+        service_id: &str,
+        start_date: DateTime<Utc>,
+        period_secs: u32,
+    ) -> Result<Option<Self>> {
+        let end_date = start_date
+            .checked_add_signed(
+                TimeDelta::new(period_secs.into(), 0)
+                    .ok_or(Error::InvalidTimeDelta(period_secs.into(), 0))?,
+            )
+            .ok_or(Error::DateOutOfRange(start_date, period_secs.into()))?;
+
+        let Some(deployment) = Self::current(railway, project_id, service_id).await? else {
+            return Ok(None);
+        };
+
+        let executions = railway
+            .paginate::<operation::Executions>(executions::Variables {
+                deployment_id: deployment.id.clone(),
+                after: None,
+            })
+            .await?
+            .into_iter()
+            .map(|execution| execution.created_at)
+            .collect::<Vec<_>>();
+        let restarts = match executions.iter().min() {
+            Some(first) => executions
+                .iter()
+                .filter(|created_at| {
+                    created_at.signed_duration_since(*first).num_seconds() > STARTUP_GRACE_SECS
+                })
+                .filter(|created_at| **created_at >= start_date && **created_at < end_date)
+                .count(),
+            None => 0,
+        };
+
+        Ok(Some(Self {
+            restarts: restarts.try_into().unwrap_or(u32::MAX),
+            ..deployment
+        }))
+    }
+
+    /// Returns the most recent deployment of the service without counting its restarts
+    pub async fn current(
+        railway: &RailwayClient,
+        project_id: &str,
+        service_id: &str,
+    ) -> Result<Option<Self>> {
+        let response = railway
+            .execute::<operation::Deployments>(deployments::Variables {
+                project_id: project_id.to_owned(),
+                service_id: service_id.to_owned(),
+            })
+            .await?;
+
+        Ok(response
+            .deployments
+            .edges
+            .into_iter()
+            .next()
+            .map(|edge| Self {
+                id: edge.node.id,
+                status: edge.node.status,
+                created_at: edge.node.created_at,
+                restarts: 0,
+            }))
+    }
+
+    /// Returns the log lines emitted by the deployment during the period
+    pub async fn logs(
+        &self,
+        railway: &RailwayClient,
+        start_date: DateTime<Utc>,
+        period_secs: u32,
+    ) -> Result<Vec<Log>> {
+        let end_date = start_date
+            .checked_add_signed(
+                TimeDelta::new(period_secs.into(), 0)
+                    .ok_or(Error::InvalidTimeDelta(period_secs.into(), 0))?,
+            )
+            .ok_or(Error::DateOutOfRange(start_date, period_secs.into()))?;
+
+        let response = railway
+            .execute::<operation::Logs>(logs::Variables {
+                deployment_id: self.id.clone(),
+                start_date,
+                end_date,
+                limit: MAX_LOG_LINES.into(),
+            })
+            .await?;
+
+        if response.deployment_logs.len() >= MAX_LOG_LINES as usize {
+            warn!(
+                "Log lines of deployment {} were truncated to {MAX_LOG_LINES}",
+                self.id
+            );
+        }
+
+        response
+            .deployment_logs
+            .into_iter()
+            .map(|log| {
+                let timestamp = log
+                    .timestamp
+                    .parse()
+                    .map_err(|err| Error::ParseDateWithMetadata(err, log.timestamp))?;
+                Ok(Log {
+                    message: log.message,
+                    severity: log.severity,
+                    timestamp,
+                })
+            })
+            .collect()
+    }
+
+    /// Restarts the instances of the deployment
+    pub async fn restart(&self, railway: &RailwayClient) -> Result<()> {
+        let response = railway
+            .execute::<operation::Restart>(restart::Variables {
+                id: self.id.clone(),
+            })
+            .await?;
+        if !response.deployment_restart {
+            return Err(Error::RailwayMutationRejected("deploymentRestart"));
+        }
+        Ok(())
+    }
+
+    /// Builds and deploys the source of the deployment again, replacing it
+    pub async fn redeploy(&self, railway: &RailwayClient) -> Result<()> {
+        railway
+            .execute::<operation::Redeploy>(redeploy::Variables {
+                id: self.id.clone(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Stops the instances of the deployment, they aren't started again until the service is deployed
+    pub async fn stop(&self, railway: &RailwayClient) -> Result<()> {
+        let response = railway
+            .execute::<operation::Stop>(stop::Variables {
+                id: self.id.clone(),
+            })
+            .await?;
+        if !response.deployment_stop {
+            return Err(Error::RailwayMutationRejected("deploymentStop"));
+        }
+        Ok(())
+    }
+}
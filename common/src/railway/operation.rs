@@ -0,0 +1,145 @@
+//! Operations of the `graphql` directory, their variables and responses are generated from the vendored schema,
+//! regenerated from the introspection of Railway's API by `common/schema.py`
+
+use graphql_client::GraphQLQuery;
+
+pub use crate::DeploymentStatus;
+
+type DateTime = chrono::DateTime<chrono::Utc>;
+type Json = serde_json::Value;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/deployments.gql",
+    normalization = "rust",
+    extern_enums("DeploymentStatus"),
+    response_derives = "Debug",
+    variables_derives = "Debug, Clone"
+)]
+pub struct Deployments;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/executions.gql",
+    normalization = "rust",
+    response_derives = "Debug",
+    variables_derives = "Debug, Clone"
+)]
+pub struct Executions;
+
+paginated!(
+    Executions,
+    executions::ExecutionsDeploymentInstanceExecutionsEdgesNode,
+    |data| data.deployment_instance_executions
+);
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/logs.gql",
+    normalization = "rust",
+    response_derives = "Debug",
+    variables_derives = "Debug, Clone"
+)]
+pub struct Logs;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/limits.gql",
+    normalization = "rust",
+    response_derives = "Debug",
+    variables_derives = "Debug, Clone"
+)]
+pub struct Limits;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/instance.gql",
+    normalization = "rust",
+    response_derives = "Debug",
+    variables_derives = "Debug, Clone"
+)]
+pub struct Instance;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/metrics.gql",
+    normalization = "rust",
+    response_derives = "Debug",
+    variables_derives = "Debug, Clone"
+)]
+pub struct Metrics;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/projects.gql",
+    normalization = "rust",
+    response_derives = "Debug",
+    variables_derives = "Debug, Clone"
+)]
+pub struct Projects;
+
+paginated!(Projects, projects::ProjectsProjectsEdgesNode, |data| data
+    .projects);
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/services.gql",
+    normalization = "rust",
+    response_derives = "Debug",
+    variables_derives = "Debug, Clone"
+)]
+pub struct Services;
+
+paginated!(
+    Services,
+    services::ServicesProjectServicesEdgesNode,
+    |data| data.project.services
+);
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/restart.gql",
+    normalization = "rust",
+    response_derives = "Debug",
+    variables_derives = "Debug, Clone"
+)]
+pub struct Restart;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/redeploy.gql",
+    normalization = "rust",
+    response_derives = "Debug",
+    variables_derives = "Debug, Clone"
+)]
+pub struct Redeploy;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/stop.gql",
+    normalization = "rust",
+    response_derives = "Debug",
+    variables_derives = "Debug, Clone"
+)]
+pub struct Stop;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema.graphql",
+    query_path = "src/graphql/replicas.gql",
+    normalization = "rust",
+    response_derives = "Debug",
+    variables_derives = "Debug, Clone"
+)]
+pub struct Replicas;
@@ -0,0 +1,65 @@
+use super::operation::{self, projects, services};
+use crate::{RailwayClient, Result};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Project {
+    id: String,
+    name: String,
+}
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectService {
+    id: String,
+    name: String,
+    /// Healthcheck path of the first instance that configures one
+    health_check_url: Option<String>,
+}
+
+impl Project {
+    /// Projects the token has access to
+    pub async fn list(railway: &RailwayClient) -> Result<Vec<Self>> {
+        let projects = railway
+            .paginate::<operation::Projects>(projects::Variables { after: None })
+            .await?;
+
+        Ok(projects
+            .into_iter()
+            .map(|project| Self {
+                id: project.id,
+                name: project.name,
+            })
+            .collect())
+    }
+
+    pub async fn services(
+        railway: &RailwayClient,
+        project_id: &str,
+    ) -> Result<Vec<ProjectService>> {
+        let services = railway
+            .paginate::<operation::Services>(services::Variables {
+                project_id: project_id.to_owned(),
+                after: None,
+            })
+            .await?;
+
+        Ok(services
+            .into_iter()
+            .map(|service| {
+                let health_check_url = service
+                    .service_instances
+                    .edges
+                    .into_iter()
+                    .find_map(|instance| instance.node.healthcheck_path);
+                ProjectService {
+                    id: service.id,
+                    name: service.name,
+                    health_check_url,
+                }
+            })
+            .collect())
+    }
+}
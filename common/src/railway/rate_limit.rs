@@ -0,0 +1,106 @@
+use std::{
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::time::Instant;
+use tracing::debug;
+
+/// Token bucket shared by every request to Railway, so bursts of alarms don't exhaust the quota of the token
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+    capacity: f64,
+    requests_per_sec: f64,
+    /// Longest the requests wait for the quota of Railway to reset before failing
+    max_wait: Duration,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    /// Set when Railway reports the quota is exhausted, no request is sent before it
+    blocked_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// Allows bursts of up to a second of requests, the rate must be positive
+    pub fn new(requests_per_sec: f64, max_wait: Duration) -> Self {
+        let capacity = requests_per_sec.max(1.);
+        Self {
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                updated_at: Instant::now(),
+                blocked_until: None,
+            }),
+            capacity,
+            requests_per_sec,
+            max_wait,
+        }
+    }
+
+    /// Waits until a request can be sent, or returns how long the quota is exhausted for if it's longer than the
+    /// maximum wait
+    pub async fn acquire(&self) -> Result<(), Duration> {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+                let now = Instant::now();
+                let elapsed = now.saturating_duration_since(bucket.updated_at);
+                bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.requests_per_sec)
+                    .min(self.capacity);
+                bucket.updated_at = now;
+
+                match bucket.blocked_until {
+                    Some(until) if until - now > self.max_wait => return Err(until - now),
+                    Some(until) if until > now => until - now,
+                    _ if bucket.tokens >= 1. => {
+                        bucket.tokens -= 1.;
+                        return Ok(());
+                    }
+                    _ => Duration::from_secs_f64((1. - bucket.tokens) / self.requests_per_sec),
+                }
+            };
+
+            debug!(
+                "Waiting {}ms to send a request to Railway",
+                wait.as_millis()
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Holds every request for the duration, unless they are already held for longer
+    pub fn block_for(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+        bucket.blocked_until = bucket.blocked_until.max(Some(until));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn acquire() {
+        let limiter = RateLimiter::new(2., Duration::from_secs(60));
+        let start = Instant::now();
+
+        // The burst is sent right away, the next request waits for a token to be refilled
+        limiter.acquire().await.unwrap();
+        limiter.acquire().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        limiter.acquire().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+
+        limiter.block_for(Duration::from_secs(10));
+        limiter.acquire().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(10_500));
+
+        // Quotas resetting after the maximum wait fail the request right away
+        limiter.block_for(Duration::from_secs(61));
+        assert_eq!(limiter.acquire().await, Err(Duration::from_secs(61)));
+        assert_eq!(start.elapsed(), Duration::from_millis(10_500));
+    }
+}
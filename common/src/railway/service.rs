@@ -0,0 +1,267 @@
+use super::operation::{self, instance, limits, metrics, metrics::MetricMeasurement, replicas};
+use crate::{Error, RailwayClient, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    #[copy]
+    cpu: f64,
+    #[copy]
+    memory_gb: f64,
+    #[copy]
+    disk_gb: f64,
+    #[copy]
+    ingress_gb: f64,
+    #[copy]
+    egress_gb: f64,
+    #[copy]
+    start_date: DateTime<Utc>,
+    #[copy]
+    end_date: DateTime<Utc>,
+    /// Deployment instance measured, `None` for the whole service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replica: Option<String>,
+    /// Limit of the measured instances, `None` if unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[copy]
+    cpu_limit_vcpus: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[copy]
+    memory_limit_gb: Option<f64>,
+}
+
+/// Resources available to each instance of the service
+#[derive(Getters, Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Limits {
+    #[serde(default, rename = "vCPUs")]
+    #[copy]
+    cpu_vcpus: Option<f64>,
+    #[serde(default, rename = "memoryGB")]
+    #[copy]
+    memory_gb: Option<f64>,
+}
+
+impl Usage {
+    /// Variables that can be used in expression alarms
+    pub const VARIABLES: [&'static str; 7] = [
+        "cpu",
+        "memory_gb",
+        "disk_gb",
+        "ingress_gb",
+        "egress_gb",
+        "cpu_limit_vcpus",
+        "memory_limit_gb",
+    ];
+
+    pub fn variable(&self, name: &str) -> Option<f64> {
+        match name {
+            "cpu" => Some(self.cpu),
+            "memory_gb" => Some(self.memory_gb),
+            "disk_gb" => Some(self.disk_gb),
+            "ingress_gb" => Some(self.ingress_gb),
+            "egress_gb" => Some(self.egress_gb),
+            "cpu_limit_vcpus" => self.cpu_limit_vcpus,
+            "memory_limit_gb" => self.memory_limit_gb,
+            _ => None,
+        }
+    }
+
+    /// Duration of the window measured
+    pub fn secs(&self) -> f64 {
+        (self.end_date - self.start_date).num_milliseconds() as f64 / 1000.
+    }
+
+    /// Railway sums the measurements of each minute in the window, this returns them per minute regardless of the window
+    pub fn rate(&self, name: &str) -> Option<f64> {
+        let value = self.variable(name)?;
+        match name {
+            "cpu_limit_vcpus" | "memory_limit_gb" => Some(value),
+            _ if self.secs() > 0. => Some(value * 60. / self.secs()),
+            _ => None,
+        }
+    }
+
+    /// The whole service can use the limit of each of its instances
+    pub fn set_limits(&mut self, limits: Limits, instances: usize) {
+        let instances = instances.max(1) as f64;
+        self.cpu_limit_vcpus = limits.cpu_vcpus.map(|limit| limit * instances);
+        self.memory_limit_gb = limits.memory_gb.map(|limit| limit * instances);
+    }
+
+    /// Same instance and limits with nothing measured during another window
+    pub fn emptied(&self, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Self {
+        Self {
+            cpu_limit_vcpus: self.cpu_limit_vcpus,
+            memory_limit_gb: self.memory_limit_gb,
+            ..Self::empty(start_date, end_date, self.replica.clone())
+        }
+    }
+
+    /// Nothing measured during the window
+    pub fn empty(
+        start_date: DateTime<Utc>,
+        end_date: DateTime<Utc>,
+        replica: Option<String>,
+    ) -> Self {
+        Self {
+            cpu: 0.,
+            memory_gb: 0.,
+            disk_gb: 0.,
+            ingress_gb: 0.,
+            egress_gb: 0.,
+            start_date,
+            end_date,
+            replica,
+            cpu_limit_vcpus: None,
+            memory_limit_gb: None,
+        }
+    }
+
+    fn add(&mut self, measurement: &MetricMeasurement, value: f64) {
+        match measurement {
+            MetricMeasurement::CpuUsage => self.cpu += value,
+            MetricMeasurement::MemoryUsageGb => self.memory_gb += value,
+            MetricMeasurement::DiskUsageGb => self.disk_gb += value,
+            MetricMeasurement::NetworkRxGb => self.ingress_gb += value,
+            MetricMeasurement::NetworkTxGb => self.egress_gb += value,
+            // Not requested by the query
+            _ => {}
+        }
+    }
+}
+
+pub struct Service;
+
+impl Service {
+    /// Returns the limits of each instance of the service, they may change at any time
+    pub async fn limits(
+        railway: &RailwayClient,
+        environment_id: &str,
+        service_id: &str,
+    ) -> Result<Limits> {
+        let response = railway
+            .execute::<operation::Limits>(limits::Variables {
+                environment_id: environment_id.to_owned(),
+                service_id: service_id.to_owned(),
+            })
+            .await?;
+
+        let limits = response.service_instance_limits;
+        Limits::deserialize(&limits).map_err(|err| Error::JsonWithMetadata(err, limits))
+    }
+
+    /// Returns the usage of the whole service and the usage of each of its replicas during each sample of the range,
+    /// oldest first, in a single query. Samples follow each other without gaps, empty if nothing was measured
+    pub async fn usage_samples(
+        railway: &RailwayClient,
+        project_id: &str,
+        service_id: &str,
+        start_date: DateTime<Utc>,
+        end_date: DateTime<Utc>,
+        sample_secs: u32,
+    ) -> Result<Vec<(Usage, Vec<Usage>)>> {
+        let response = railway
+            .execute::<operation::Metrics>(metrics::Variables {
+                project_id: project_id.to_owned(),
+                service_id: service_id.to_owned(),
+                start_date,
+                end_date,
+                sample_rate_seconds: sample_secs.into(),
+            })
+            .await?;
+
+        let sample_secs = i64::from(sample_secs);
+        let count = (end_date - start_date).num_seconds() / sample_secs;
+        let mut samples = (0..count.max(0))
+            .map(|index| {
+                let start_date = start_date + TimeDelta::seconds(index * sample_secs);
+                let end_date = start_date + TimeDelta::seconds(sample_secs);
+                (Usage::empty(start_date, end_date, None), Vec::new())
+            })
+            .collect::<Vec<_>>();
+
+        // Railway averages the measurements over each sample, they're summed per minute like in a window
+        let minutes = sample_secs as f64 / 60.;
+        let mut any = false;
+        for metric in response.metrics {
+            for value in metric.values {
+                let index = (value.ts - start_date.timestamp()).div_euclid(sample_secs);
+                let Some((service, replicas)) = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| samples.get_mut(index))
+                else {
+                    continue;
+                };
+                any = true;
+                service.add(&metric.measurement, value.value * minutes);
+
+                // Volume measurements aren't attributed to a specific replica
+                if let Some(replica) = &metric.tags.deployment_instance_id {
+                    let index = match replicas
+                        .iter()
+                        .position(|u: &Usage| u.replica.as_ref() == Some(replica))
+                    {
+                        Some(index) => index,
+                        None => {
+                            replicas.push(Usage::empty(
+                                service.start_date,
+                                service.end_date,
+                                Some(replica.clone()),
+                            ));
+                            replicas.len() - 1
+                        }
+                    };
+                    replicas[index].add(&metric.measurement, value.value * minutes);
+                }
+            }
+        }
+
+        if !any {
+            warn!("No measurements collected for service {service_id}");
+        }
+
+        Ok(samples)
+    }
+
+    /// Returns the number of instances of the service in the environment
+    pub async fn replicas(
+        railway: &RailwayClient,
+        environment_id: &str,
+        service_id: &str,
+    ) -> Result<u16> {
+        let response = railway
+            .execute::<operation::Instance>(instance::Variables {
+                environment_id: environment_id.to_owned(),
+                service_id: service_id.to_owned(),
+            })
+            .await?;
+
+        // Unset until the replicas are changed, services start with a single one
+        let replicas = response.service_instance.num_replicas.unwrap_or(1);
+        Ok(replicas.try_into().unwrap_or(u16::MAX))
+    }
+
+    /// Sets the number of instances of the service in the environment, they are added or removed right away
+    pub async fn set_replicas(
+        railway: &RailwayClient,
+        environment_id: &str,
+        service_id: &str,
+        replicas: u16,
+    ) -> Result<()> {
+        let response = railway
+            .execute::<operation::Replicas>(replicas::Variables {
+                environment_id: environment_id.to_owned(),
+                service_id: service_id.to_owned(),
+                replicas: replicas.into(),
+            })
+            .await?;
+        if !response.service_instance_update {
+            return Err(Error::RailwayMutationRejected("serviceInstanceUpdate"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{Error, Result, Transport};
+use async_trait::async_trait;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, PoisonError},
+};
+
+/// Railway GraphQL API answering with the responses queued for each operation, like `usage` or `deployments`
+#[derive(Default)]
+pub struct FakeRailway {
+    responses: Mutex<HashMap<String, VecDeque<Result<serde_json::Value, u16>>>>,
+    requests: Mutex<Vec<(String, serde_json::Value)>>,
+}
+
+impl FakeRailway {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues the data of a response, the last one queued is repeated once the others are consumed
+    pub fn respond(&self, operation: &str, data: serde_json::Value) {
+        self.queue(operation, Ok(data));
+    }
+
+    /// Queues a response failing with the HTTP status
+    pub fn fail(&self, operation: &str, status: u16) {
+        self.queue(operation, Err(status));
+    }
+
+    /// Variables of every request of the operation received so far
+    pub fn requests(&self, operation: &str) -> Vec<serde_json::Value> {
+        self.requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|(name, _)| name == operation)
+            .map(|(_, variables)| variables.clone())
+            .collect()
+    }
+
+    fn queue(&self, operation: &str, response: Result<serde_json::Value, u16>) {
+        self.responses
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(operation.to_owned())
+            .or_default()
+            .push_back(response);
+    }
+}
+
+#[async_trait]
+impl Transport for FakeRailway {
+    async fn send(&self, _token: &str, json: &serde_json::Value) -> Result<serde_json::Value> {
+        let query = json["query"].as_str().unwrap_or_default();
+        let operation = query
+            .trim_start()
+            .trim_start_matches("query")
+            .trim_start_matches("mutation")
+            .trim_start()
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+        self.requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((operation.clone(), json["variables"].clone()));
+
+        let response = {
+            let mut responses = self
+                .responses
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let queue = responses.entry(operation.clone()).or_default();
+            if queue.len() > 1 {
+                queue.pop_front()
+            } else {
+                queue.front().cloned()
+            }
+        };
+
+        match response {
+            Some(Ok(data)) => Ok(serde_json::json!({ "data": data })),
+            Some(Err(status)) => Err(Error::RailwayStatusFailure(
+                status,
+                format!("{operation} failed"),
+            )),
+            None => Err(Error::Railway(vec![format!(
+                "no response queued for {operation}"
+            )])),
+        }
+    }
+}